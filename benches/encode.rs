@@ -0,0 +1,62 @@
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use haproxy_spoa_example::{
+    AckFrame, AckFramePayload, Action, ActionVarScope, FrameCodec, FrameHeader, FramePayload,
+    TypedData, Varint, VarintString,
+};
+use std::net::Ipv4Addr;
+
+fn ack(nb_actions: usize) -> (FrameHeader, FramePayload) {
+    let actions = (0..nb_actions)
+        .map(|i| {
+            let value = match i % 3 {
+                0 => TypedData::STRING(VarintString::new("var-value-1")),
+                1 => TypedData::UINT32(1_000_000),
+                _ => TypedData::IPV4(Ipv4Addr::new(127, 0, 0, 1)),
+            };
+            Action::set_val(
+                ActionVarScope::TRANSACTION,
+                VarintString::new(&format!("var_name_{}", i)),
+                value,
+            )
+        })
+        .collect();
+
+    AckFrame::new(
+        Varint::from(123_456u64),
+        Varint::from(7u64),
+        AckFramePayload::new(actions),
+    )
+    .into()
+}
+
+fn encode_ack(c: &mut Criterion) {
+    let codec = FrameCodec::default();
+    let mut group = c.benchmark_group("encode_ack");
+
+    for nb_actions in [1, 16] {
+        let (frame_header, frame_payload) = ack(nb_actions);
+        let mut dst = BytesMut::with_capacity(4096);
+
+        group.bench_with_input(
+            BenchmarkId::new("direct", nb_actions),
+            &nb_actions,
+            |b, _| {
+                b.iter(|| {
+                    dst.clear();
+                    codec
+                        .encode_frame_with(&mut dst, |buf| {
+                            frame_header.write_to(buf);
+                            frame_payload.write_to(buf);
+                        })
+                        .unwrap();
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, encode_ack);
+criterion_main!(benches);
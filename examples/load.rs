@@ -0,0 +1,149 @@
+use anyhow::{anyhow, bail};
+use bytes::BytesMut;
+use haproxy_spoa_example::{
+    FrameCodec, FrameFlags, FrameHeader, FramePayload, FrameType, ListenAddr, MessageArgs,
+    TypedData, Varint, VarintString,
+};
+use std::env;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Pipelined NOTIFY frames against a running agent, e.g.
+//
+// cargo run --release --example load -- unix@haproxy_run/spoa_demo.sock 8 32 10
+//
+// opens 8 connections that each keep 32 NOTIFY frames in flight for 10 seconds, then prints
+// the ACK frames received per second. Only SPOP goes over the socket, so any version of the
+// agent can be measured.
+
+const USAGE: &str = "usage: load <listen-addr> <connections> <depth> <seconds>";
+
+const HAPROXY_HELLO: &[u8] = b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x10pipelining,async\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57";
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+fn connect(addr: &ListenAddr) -> anyhow::Result<Box<dyn Stream>> {
+    match addr {
+        ListenAddr::Tcp(addr) => {
+            let stream = TcpStream::connect(addr)?;
+            stream.set_nodelay(true)?;
+            Ok(Box::new(stream))
+        }
+        ListenAddr::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+        addr => bail!("cannot connect to {}", addr),
+    }
+}
+
+fn write_frame(stream: &mut dyn Stream, frame: &[u8]) -> anyhow::Result<()> {
+    stream.write_all(&(frame.len() as u32).to_be_bytes())?;
+    stream.write_all(frame)?;
+    Ok(())
+}
+
+fn read_frame(stream: &mut dyn Stream) -> anyhow::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+// `depth` NOTIFY frames for the msg-1 message of haproxy_conf, written at once.
+fn notify_frames(depth: usize) -> anyhow::Result<BytesMut> {
+    let codec = FrameCodec::default();
+    let mut buf = BytesMut::new();
+    for frame_id in 1..=depth {
+        let frame_header = FrameHeader {
+            r#type: FrameType::NOTIFY,
+            flags: FrameFlags::new(true, false),
+            stream_id: Varint::from(0u64),
+            frame_id: Varint::from(frame_id as u64),
+        };
+        let mut args = MessageArgs::new();
+        args.push(
+            VarintString::new("arg_method"),
+            TypedData::STRING(VarintString::new("GET")),
+        );
+        args.push(
+            VarintString::new("arg_path"),
+            TypedData::STRING(VarintString::new("/")),
+        );
+        let frame_payload =
+            FramePayload::LIST_OF_MESSAGES(vec![(VarintString::new("msg-1"), args)]);
+
+        codec.encode_frame_with(&mut buf, |buf| {
+            frame_header.write_to(buf);
+            frame_payload.write_to(buf);
+        })?;
+    }
+
+    Ok(buf)
+}
+
+// Returns the number of ACK frames received.
+fn run(addr: &ListenAddr, depth: usize, duration: Duration) -> anyhow::Result<u64> {
+    let mut stream = connect(addr)?;
+    write_frame(&mut *stream, HAPROXY_HELLO)?;
+    let agent_hello = read_frame(&mut *stream)?;
+    if agent_hello[0] != u8::from(FrameType::AGENT_HELLO) {
+        bail!("expected AGENT-HELLO, got frame type {}", agent_hello[0]);
+    }
+
+    let notify_frames = notify_frames(depth)?;
+    let started = Instant::now();
+    let mut acks = 0;
+    while started.elapsed() < duration {
+        stream.write_all(&notify_frames)?;
+        for _ in 0..depth {
+            let ack = read_frame(&mut *stream)?;
+            if ack[0] != u8::from(FrameType::ACK) {
+                bail!("expected ACK, got frame type {}", ack[0]);
+            }
+        }
+        acks += depth as u64;
+    }
+
+    Ok(acks)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 4 {
+        bail!(USAGE);
+    }
+    let addr: ListenAddr = args[0].parse()?;
+    let connections: usize = args[1].parse().map_err(|_| anyhow!(USAGE))?;
+    let depth: usize = args[2].parse().map_err(|_| anyhow!(USAGE))?;
+    let seconds: u64 = args[3].parse().map_err(|_| anyhow!(USAGE))?;
+    if connections == 0 || depth == 0 || seconds == 0 {
+        bail!(USAGE);
+    }
+
+    let duration = Duration::from_secs(seconds);
+    let handles: Vec<_> = (0..connections)
+        .map(|_| {
+            let addr = addr.clone();
+            thread::spawn(move || run(&addr, depth, duration))
+        })
+        .collect();
+
+    let mut acks = 0;
+    for handle in handles {
+        acks += handle
+            .join()
+            .map_err(|_| anyhow!("connection panicked"))??;
+    }
+
+    println!(
+        "{} ACKs/s, {} connections with {} NOTIFY frames in flight",
+        acks / seconds,
+        connections,
+        depth
+    );
+
+    Ok(())
+}
@@ -5,7 +5,7 @@ use std::convert::{TryFrom, TryInto};
 use std::str;
 use thiserror::Error;
 
-#[derive(Clone, Debug)]
+#[derive(PartialEq, Clone, Debug)]
 #[allow(non_camel_case_types)]
 pub enum Action {
     SET_VAR {
@@ -44,14 +44,20 @@ pub enum ActionType {
     UNSET_VAR = 2,
 }
 
-#[derive(IntoPrimitive, TryFromPrimitive, PartialEq, Eq, Clone, Debug)]
+// Parsed from the HAProxy variable scope names, e.g. `txn` as in `var(txn.spoe_demo.var_name_1)`
+#[derive(IntoPrimitive, TryFromPrimitive, EnumString, PartialEq, Eq, Clone, Debug)]
 #[repr(u8)]
 #[allow(non_camel_case_types)]
 pub enum ActionVarScope {
+    #[strum(serialize = "proc")]
     PROCESS = 0,
+    #[strum(serialize = "sess")]
     SESSION = 1,
+    #[strum(serialize = "txn")]
     TRANSACTION = 2,
+    #[strum(serialize = "req")]
     REQUEST = 3,
+    #[strum(serialize = "res")]
     RESPONSE = 4,
 }
 
@@ -80,7 +86,7 @@ impl TryFrom<&mut Bytes> for Action {
     type Error = ActionParseError;
 
     fn try_from(bytes: &mut Bytes) -> Result<Self, ActionParseError> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(ActionParseError::InsufficientBytes);
         }
         let b = bytes.split_to(1);
@@ -103,7 +109,7 @@ impl TryFrom<&mut Bytes> for Action {
             }
         }
 
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(ActionParseError::InsufficientBytes);
         }
         let b = bytes.split_to(1);
@@ -148,11 +154,38 @@ impl Action {
                 var_scope,
                 var_name,
             } => {
-                buf.put_u8(ActionType::SET_VAR.into());
-                buf.put_u8(3);
+                buf.put_u8(ActionType::UNSET_VAR.into());
+                buf.put_u8(2);
                 buf.put_u8(var_scope.to_owned().into());
                 var_name.write_to(buf);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_to() -> anyhow::Result<()> {
+        for action in [
+            Action::set_val(
+                ActionVarScope::TRANSACTION,
+                VarintString::new("method"),
+                TypedData::STRING(VarintString::new("GET")),
+            ),
+            Action::unset_val(ActionVarScope::SESSION, VarintString::new("method")),
+        ] {
+            let mut buf = BytesMut::new();
+            action.write_to(&mut buf);
+            assert_eq!(Action::try_from(&mut buf.freeze())?, action);
+        }
+
+        let mut buf = BytesMut::new();
+        Action::unset_val(ActionVarScope::REQUEST, VarintString::new("a")).write_to(&mut buf);
+        assert_eq!(&buf[..], b"\x02\x02\x03\x01a");
+
+        Ok(())
+    }
+}
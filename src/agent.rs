@@ -0,0 +1,473 @@
+use crate::{
+    Action, AgentState, Connection, ConnectionContext, ConnectionEvent, ListenAddr,
+    PeerCredentials, Varint,
+};
+use bytes::{Buf, Bytes};
+use futures::channel::mpsc;
+use futures::future::{self, BoxFuture};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures::stream::FuturesUnordered;
+use futures::{pin_mut, select, FutureExt, SinkExt, StreamExt};
+use futures_timer::Delay;
+use log::*;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, IoSlice};
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// What the connection loop needs from the async runtime, the listeners and the framing
+// are set up by `runtime::smol` or `runtime::tokio`.
+pub(crate) trait Runtime {
+    // The task is cancelled when the returned future is dropped.
+    fn spawn<T: Send + 'static>(future: BoxFuture<'static, T>) -> BoxFuture<'static, T>;
+    fn spawn_detached(future: BoxFuture<'static, ()>);
+}
+
+// Checked with SO_PEERCRED on unix listeners before any frame is read.
+pub(crate) fn is_peer_allowed<S: AsRawFd>(
+    stream: &S,
+    agent_state: &AgentState,
+    addr: &ListenAddr,
+) -> bool {
+    let allowed_peers = &agent_state.config().allowed_peers;
+    if allowed_peers.is_empty() {
+        return true;
+    }
+
+    match PeerCredentials::from_unix_stream(stream) {
+        Ok(peer) if allowed_peers.allows(&peer) => {
+            debug!("peer {:?} allowed on {}", peer, addr);
+            true
+        }
+        Ok(peer) => {
+            warn!("peer {:?} not allowed on {}, disconnected", peer, addr);
+            false
+        }
+        Err(e) => {
+            warn!("peer credentials on {}: {}, disconnected", addr, e);
+            false
+        }
+    }
+}
+
+// Lets the file descriptors or the memory the listener ran out of be released.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Keeps the listener going after an accept error that concerns a single connection, or a
+// lack of file descriptors or memory that clears as connections close. Any other error
+// ends the listener.
+pub(crate) async fn accept_failed(e: io::Error, addr: &ListenAddr) -> io::Result<()> {
+    match e.raw_os_error() {
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS) | Some(libc::ENOMEM) => {
+            warn!(
+                "accept on {} failed: {}, retrying in {:?}",
+                addr, e, ACCEPT_BACKOFF
+            );
+            Delay::new(ACCEPT_BACKOFF).await;
+            Ok(())
+        }
+        Some(libc::EPROTO) | Some(libc::EPERM) => {
+            warn!("accept on {} failed: {}", addr, e);
+            Ok(())
+        }
+        _ => match e.kind() {
+            io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut => {
+                warn!("accept on {} failed: {}", addr, e);
+                Ok(())
+            }
+            _ => Err(e),
+        },
+    }
+}
+
+// `connection` also finishes setting up the transport, e.g. the TLS handshake, in the
+// connection task so that the listener keeps accepting meanwhile.
+pub(crate) fn spawn_connection<R, C>(connection: C, agent_state: &AgentState)
+where
+    R: Runtime,
+    C: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let guard = match agent_state.shutdown().connection_guard() {
+        Some(guard) => guard,
+        None => return,
+    };
+
+    R::spawn_detached(
+        async move {
+            if let Err(e) = connection.await {
+                error!("connection error: {:?}", e)
+            } else {
+                info!("connection closed")
+            }
+            drop(guard);
+        }
+        .boxed(),
+    );
+}
+
+enum Event {
+    Read(io::Result<usize>),
+    Ack((Varint, Varint, Vec<Action>)),
+    Timeout,
+    Shutdown,
+    WriterStopped(io::Result<()>),
+}
+
+const READ_BUFFER_SIZE: usize = 16384;
+// Frames waiting for the writer, the connection stops reading once it is full.
+const WRITE_QUEUE_SIZE: usize = 64;
+
+// Drives `Connection` with the stream, the message handlers and the timers. The frames
+// are written by another task, so that reading goes on while the writes are pending.
+pub(crate) async fn connection_loop<R, S>(
+    stream: S,
+    agent_state: Arc<AgentState>,
+    context: ConnectionContext,
+) -> anyhow::Result<()>
+where
+    R: Runtime,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let shutdown = agent_state.shutdown().requested().fuse();
+    pin_mut!(shutdown);
+
+    let (mut reader, writer) = stream.split();
+    let (mut queue, queued) = mpsc::channel(WRITE_QUEUE_SIZE);
+    let mut writer = R::spawn(write_loop(writer, queued).boxed()).fuse();
+
+    let mut connection = Connection::new(agent_state, context, Instant::now());
+
+    let mut in_flight = FuturesUnordered::new();
+    let mut buf = vec![0; READ_BUFFER_SIZE];
+    let mut error: Option<anyhow::Error> = None;
+    let mut written = None;
+
+    'connection: loop {
+        while let Some(bytes) = connection.poll_transmit() {
+            if queue.send(bytes).await.is_err() {
+                // The writer failed, its error is returned below.
+                break 'connection;
+            }
+        }
+
+        let mut do_close = false;
+        while let Some(event) = connection.poll_event() {
+            match event {
+                ConnectionEvent::NotifyReceived(notify_frame) => {
+                    let (stream_id, frame_id) = (notify_frame.stream_id, notify_frame.frame_id);
+                    let actions = connection.frame().dispatch(notify_frame.payload.messages);
+                    // Spawned so that the handlers of a connection run on every executor thread.
+                    in_flight.push(R::spawn(
+                        async move { (stream_id, frame_id, actions.await) }.boxed(),
+                    ));
+                }
+                ConnectionEvent::DisconnectRequested => do_close = true,
+            }
+        }
+        if do_close || connection.is_closed() {
+            break;
+        }
+
+        let deadline = connection.poll_timeout();
+        let timer = async move {
+            match deadline {
+                Some(deadline) => {
+                    Delay::new(deadline.saturating_duration_since(Instant::now())).await;
+                }
+                None => future::pending::<()>().await,
+            }
+        }
+        .fuse();
+        pin_mut!(timer);
+
+        let event = select! {
+            n = reader.read(&mut buf).fuse() => Event::Read(n),
+            ack = in_flight.select_next_some() => Event::Ack(ack),
+            _ = timer => Event::Timeout,
+            _ = shutdown => Event::Shutdown,
+            r = writer => Event::WriterStopped(r),
+        };
+
+        let now = Instant::now();
+        let result = match event {
+            Event::Read(Ok(0)) => {
+                connection.closed();
+                Ok(())
+            }
+            Event::Read(Ok(n)) => connection.handle_input(now, &buf[..n]),
+            Event::Read(Err(e)) => {
+                error = Some(e.into());
+                break 'connection;
+            }
+            Event::Ack((stream_id, frame_id, actions)) => {
+                connection.ack(now, stream_id, frame_id, actions)
+            }
+            Event::Timeout => {
+                connection.handle_timeout(now);
+                Ok(())
+            }
+            Event::Shutdown => {
+                connection.shutdown(now);
+                Ok(())
+            }
+            Event::WriterStopped(r) => {
+                written = Some(r);
+                break 'connection;
+            }
+        };
+        // The AGENT-DISCONNECT frame is sent first.
+        if let Err(e) = result {
+            error = Some(e.into());
+        }
+    }
+
+    if !in_flight.is_empty() {
+        info!(
+            "dropping {} NOTIFY frames still being processed",
+            in_flight.len()
+        );
+    }
+
+    // The writer stops once the queue is drained and closed.
+    drop(queue);
+    let written = match written {
+        Some(written) => written,
+        None => writer.await,
+    };
+    connection.closed();
+    written?;
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Frames queued while a write is pending are coalesced into the next vectored write.
+async fn write_loop<W>(mut writer: W, mut queued: mpsc::Receiver<Bytes>) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut bufs = VecDeque::new();
+    let mut closed = false;
+    while !closed {
+        match queued.next().await {
+            Some(bytes) => bufs.push_back(bytes),
+            None => break,
+        }
+        while bufs.len() < WRITE_QUEUE_SIZE {
+            match queued.next().now_or_never() {
+                Some(Some(bytes)) => bufs.push_back(bytes),
+                Some(None) => {
+                    closed = true;
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        write_all_vectored(&mut writer, &mut bufs)
+            .await
+            .map_err(|e| {
+                error!("on send {:?}", e);
+                e
+            })?;
+    }
+
+    writer.flush().await.map_err(|e| {
+        error!("on flush {:?}", e);
+        e
+    })?;
+    writer.close().await.map_err(|e| {
+        error!("on close {:?}", e);
+        e
+    })
+}
+
+async fn write_all_vectored<W>(writer: &mut W, bufs: &mut VecDeque<Bytes>) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while !bufs.is_empty() {
+        let slices = bufs.iter().map(|buf| IoSlice::new(buf)).collect::<Vec<_>>();
+        let mut n = writer.write_vectored(&slices).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        debug!("write len: {} in {} frames", n, slices.len());
+
+        while let Some(buf) = bufs.front_mut() {
+            if n < buf.len() {
+                buf.advance(n);
+                break;
+            }
+            n -= buf.len();
+            bufs.pop_front();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll};
+
+    // Spawned futures run within the connection loop.
+    struct Inline;
+
+    impl Runtime for Inline {
+        fn spawn<T: Send + 'static>(future: BoxFuture<'static, T>) -> BoxFuture<'static, T> {
+            future
+        }
+
+        fn spawn_detached(_: BoxFuture<'static, ()>) {}
+    }
+
+    #[derive(Default)]
+    struct Writes {
+        writes: Vec<Vec<u8>>,
+        closed: bool,
+    }
+
+    // Writes at most 5 bytes at a time, to check partial writes.
+    impl AsyncWrite for Writes {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+        }
+
+        fn poll_write_vectored(
+            mut self: Pin<&mut Self>,
+            _: &mut Context,
+            bufs: &[IoSlice],
+        ) -> Poll<io::Result<usize>> {
+            let write = bufs
+                .iter()
+                .flat_map(|buf| buf.iter())
+                .take(5)
+                .copied()
+                .collect::<Vec<_>>();
+            let n = write.len();
+            self.writes.push(write);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            self.closed = true;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    // Every read fails, the writes are kept.
+    #[derive(Clone, Default)]
+    struct ResetStream(Arc<Mutex<Writes>>);
+
+    impl AsyncRead for ResetStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context,
+            _: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+        }
+    }
+
+    impl AsyncWrite for ResetStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut *self.0.lock().unwrap()).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut *self.0.lock().unwrap()).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut *self.0.lock().unwrap()).poll_close(cx)
+        }
+    }
+
+    #[test]
+    fn test_connection_loop_read_failed() -> anyhow::Result<()> {
+        let stream = ResetStream::default();
+        let e = block_on(connection_loop::<Inline, _>(
+            stream.clone(),
+            Arc::new(AgentState::default()),
+            ConnectionContext::default(),
+        ))
+        .err()
+        .unwrap();
+        assert_eq!(
+            e.downcast_ref::<io::Error>().map(|e| e.kind()),
+            Some(io::ErrorKind::ConnectionReset)
+        );
+
+        // The writer is stopped before returning.
+        assert!(stream.0.lock().unwrap().closed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_loop() -> anyhow::Result<()> {
+        let (mut queue, queued) = mpsc::channel(WRITE_QUEUE_SIZE);
+        for bytes in &["abc", "defg", "hi"] {
+            queue.try_send(Bytes::from_static(bytes.as_bytes()))?;
+        }
+        drop(queue);
+
+        let mut writes = Writes::default();
+        block_on(write_loop(&mut writes, queued))?;
+
+        // The 3 frames are written together, up to what the writer takes.
+        assert_eq!(writes.writes, vec![b"abcde".to_vec(), b"fghi".to_vec()]);
+        assert!(writes.closed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_accept_failed() -> anyhow::Result<()> {
+        let addr: ListenAddr = "ipv4@127.0.0.1:6001".parse()?;
+
+        let started = Instant::now();
+        block_on(accept_failed(
+            io::Error::from_raw_os_error(libc::EMFILE),
+            &addr,
+        ))?;
+        assert!(started.elapsed() >= ACCEPT_BACKOFF);
+
+        block_on(accept_failed(
+            io::Error::from_raw_os_error(libc::ECONNABORTED),
+            &addr,
+        ))?;
+
+        let e = block_on(accept_failed(
+            io::Error::from_raw_os_error(libc::EBADF),
+            &addr,
+        ));
+        assert_eq!(e.map_err(|e| e.raw_os_error()), Err(Some(libc::EBADF)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{
+    AllowedPeers, HAProxyHelloFrameCapability, ListenAddr, SupportVersion, TlsServerConfig,
+    UnixSocketOptions, DEFAULT_MAX_FRAME_SIZE, DEFAULT_UNIX_SOCKET_PATH, MIN_FRAME_SIZE,
+};
+use semver::Version;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+pub const DEFAULT_MAX_FRAGMENTED_FRAME_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct AgentConfig {
+    // Every address is served in parallel with the same message handlers.
+    pub listen_addrs: Vec<ListenAddr>,
+    // Mode and owner of the `unix@` socket files.
+    pub unix_socket_options: UnixSocketOptions,
+    // Checked with SO_PEERCRED on every unix listener before any frame is read.
+    pub allowed_peers: AllowedPeers,
+    // Serves the TCP listeners over TLS when set.
+    pub tls: Option<TlsServerConfig>,
+    // Executor threads, connections and pipelined NOTIFY frames are spread across them.
+    pub threads: usize,
+    // Worker processes serving the same listeners, each with its own executor threads.
+    // With more than one, this process only supervises them.
+    pub workers: usize,
+    // Every worker binds its own TCP listeners with SO_REUSEPORT and the kernel spreads
+    // the connections across them, instead of sharing a single listening socket.
+    pub reuse_port: bool,
+    // The highest version also listed in HAPROXY-HELLO supported-versions is used.
+    pub supported_versions: Vec<SupportVersion>,
+    // Lowered to the max-frame-size sent in HAPROXY-HELLO.
+    pub max_frame_size: u32,
+    // Per connection, frames fragmented beyond it fail with resource_allocation_error.
+    pub max_fragmented_frame_size: usize,
+    // Intersected with the capabilities sent in HAPROXY-HELLO.
+    pub capabilities: Vec<HAProxyHelloFrameCapability>,
+    // Time allowed to receive HAPROXY-HELLO once connected.
+    pub hello_timeout: Option<Duration>,
+    // Time a connection may stay without any frame while no NOTIFY is being processed.
+    pub idle_timeout: Option<Duration>,
+    // Time allowed to a message handler, its actions are dropped past it.
+    pub processing_timeout: Option<Duration>,
+    // Time allowed to pending NOTIFY frames on shutdown, before AGENT-DISCONNECT is sent.
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            listen_addrs: vec![ListenAddr::Unix(PathBuf::from(DEFAULT_UNIX_SOCKET_PATH))],
+            unix_socket_options: UnixSocketOptions::default(),
+            allowed_peers: AllowedPeers::default(),
+            tls: None,
+            threads: 1,
+            workers: 1,
+            reuse_port: false,
+            supported_versions: vec![SupportVersion::new(Version::new(2, 0, 0))],
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_fragmented_frame_size: DEFAULT_MAX_FRAGMENTED_FRAME_SIZE,
+            capabilities: vec![
+                HAProxyHelloFrameCapability::r#async,
+                HAProxyHelloFrameCapability::pipelining,
+                HAProxyHelloFrameCapability::fragmentation,
+            ],
+            hello_timeout: None,
+            idle_timeout: None,
+            processing_timeout: None,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub enum AgentConfigError {
+    #[error("no listen address")]
+    NoListenAddr,
+    #[error("listen address {0} is configured twice")]
+    DuplicateListenAddr(ListenAddr),
+    #[error("threads must be at least 1")]
+    NoThreads,
+    #[error("workers must be at least 1")]
+    NoWorkers,
+    #[error("no supported version")]
+    NoSupportedVersion,
+    #[error("max-frame-size {0} is less than {}", MIN_FRAME_SIZE)]
+    MaxFrameSizeTooSmall(u32),
+    #[error("max-fragmented-frame-size {0} is less than max-frame-size {1}")]
+    MaxFragmentedFrameSizeTooSmall(usize, u32),
+}
+
+impl AgentConfig {
+    pub fn validate(&self) -> Result<(), AgentConfigError> {
+        if self.listen_addrs.is_empty() {
+            return Err(AgentConfigError::NoListenAddr);
+        }
+        for (i, listen_addr) in self.listen_addrs.iter().enumerate() {
+            if self.listen_addrs[..i].contains(listen_addr) {
+                return Err(AgentConfigError::DuplicateListenAddr(listen_addr.clone()));
+            }
+        }
+        if self.threads == 0 {
+            return Err(AgentConfigError::NoThreads);
+        }
+        if self.workers == 0 {
+            return Err(AgentConfigError::NoWorkers);
+        }
+        if self.supported_versions.is_empty() {
+            return Err(AgentConfigError::NoSupportedVersion);
+        }
+        if self.max_frame_size < MIN_FRAME_SIZE {
+            return Err(AgentConfigError::MaxFrameSizeTooSmall(self.max_frame_size));
+        }
+        if self.max_fragmented_frame_size < self.max_frame_size as usize {
+            return Err(AgentConfigError::MaxFragmentedFrameSizeTooSmall(
+                self.max_fragmented_frame_size,
+                self.max_frame_size,
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn negotiate_version(
+        &self,
+        haproxy_supported_versions: &[SupportVersion],
+    ) -> Option<SupportVersion> {
+        self.supported_versions
+            .iter()
+            .filter(|x| haproxy_supported_versions.contains(x))
+            .max()
+            .cloned()
+    }
+
+    pub fn negotiate_max_frame_size(&self, haproxy_max_frame_size: u32) -> Option<u32> {
+        let max_frame_size = self.max_frame_size.min(haproxy_max_frame_size);
+
+        if max_frame_size < MIN_FRAME_SIZE {
+            return None;
+        }
+
+        Some(max_frame_size)
+    }
+
+    pub fn negotiate_capabilities(
+        &self,
+        haproxy_capabilities: &[HAProxyHelloFrameCapability],
+    ) -> Vec<HAProxyHelloFrameCapability> {
+        self.capabilities
+            .iter()
+            .filter(|x| haproxy_capabilities.contains(x))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_version() -> anyhow::Result<()> {
+        let v = |s: &str| SupportVersion::parse(s).unwrap();
+
+        let config = AgentConfig {
+            supported_versions: vec![v("1.0"), v("2.0"), v("2.1")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.negotiate_version(&[v("2.0"), v("1.0")]),
+            Some(v("2.0"))
+        );
+        assert_eq!(
+            config.negotiate_version(&[v("2.1"), v("2.0")]),
+            Some(v("2.1"))
+        );
+        assert_eq!(config.negotiate_version(&[v("3.0")]), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negotiate_max_frame_size() -> anyhow::Result<()> {
+        let config = AgentConfig {
+            max_frame_size: 1024,
+            ..Default::default()
+        };
+
+        assert_eq!(config.negotiate_max_frame_size(16380), Some(1024));
+        assert_eq!(config.negotiate_max_frame_size(512), Some(512));
+        assert_eq!(config.negotiate_max_frame_size(MIN_FRAME_SIZE - 1), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negotiate_capabilities() -> anyhow::Result<()> {
+        let config = AgentConfig {
+            capabilities: vec![
+                HAProxyHelloFrameCapability::pipelining,
+                HAProxyHelloFrameCapability::fragmentation,
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.negotiate_capabilities(&[
+                HAProxyHelloFrameCapability::r#async,
+                HAProxyHelloFrameCapability::pipelining,
+            ]),
+            vec![HAProxyHelloFrameCapability::pipelining]
+        );
+        assert_eq!(config.negotiate_capabilities(&[]), vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> anyhow::Result<()> {
+        assert_eq!(AgentConfig::default().validate(), Ok(()));
+
+        let config = AgentConfig {
+            listen_addrs: vec![],
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(AgentConfigError::NoListenAddr));
+
+        let listen_addr: ListenAddr = "ipv4@127.0.0.1:6001".parse()?;
+        let config = AgentConfig {
+            listen_addrs: vec![listen_addr.clone(), listen_addr.clone()],
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(AgentConfigError::DuplicateListenAddr(listen_addr))
+        );
+
+        let config = AgentConfig {
+            threads: 0,
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(AgentConfigError::NoThreads));
+
+        let config = AgentConfig {
+            workers: 0,
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(AgentConfigError::NoWorkers));
+
+        let config = AgentConfig {
+            max_frame_size: MIN_FRAME_SIZE - 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(AgentConfigError::MaxFrameSizeTooSmall(MIN_FRAME_SIZE - 1))
+        );
+
+        Ok(())
+    }
+}
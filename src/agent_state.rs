@@ -0,0 +1,93 @@
+use crate::{AgentConfig, MessageHandlers, Shutdown};
+use log::*;
+use std::sync::{Arc, RwLock};
+
+// The config and message handlers the agent serves with, swapped as a whole on reload.
+//
+// A connection keeps the config it negotiated HAPROXY-HELLO with, but every NOTIFY frame
+// is dispatched to the handlers current at the time it is received.
+#[derive(Debug, Default)]
+pub struct AgentState {
+    current: RwLock<(Arc<AgentConfig>, Arc<MessageHandlers>)>,
+    shutdown: Shutdown,
+}
+
+impl AgentState {
+    pub fn new(config: AgentConfig, handlers: MessageHandlers) -> Self {
+        Self {
+            current: RwLock::new((Arc::new(config), Arc::new(handlers))),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    pub fn config(&self) -> Arc<AgentConfig> {
+        self.current.read().unwrap().0.clone()
+    }
+
+    pub fn handlers(&self) -> Arc<MessageHandlers> {
+        self.current.read().unwrap().1.clone()
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    // The listeners are already bound and the executor started, so listen addresses,
+    // threads and workers only change on restart.
+    pub fn reload(&self, mut config: AgentConfig, handlers: MessageHandlers) {
+        let mut current = self.current.write().unwrap();
+
+        if config.listen_addrs != current.0.listen_addrs {
+            warn!("listen addresses changed, they are only applied on restart");
+            config.listen_addrs = current.0.listen_addrs.clone();
+        }
+        if (config.threads, config.workers, config.reuse_port)
+            != (current.0.threads, current.0.workers, current.0.reuse_port)
+        {
+            warn!("threads, workers and reuse-port changed, they are only applied on restart");
+            config.threads = current.0.threads;
+            config.workers = current.0.workers;
+            config.reuse_port = current.0.reuse_port;
+        }
+
+        *current = (Arc::new(config), Arc::new(handlers));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConnectionContext, MessageArgs};
+
+    #[test]
+    fn test_reload() -> anyhow::Result<()> {
+        let state = AgentState::new(AgentConfig::default(), MessageHandlers::new());
+        let config = state.config();
+
+        let mut handlers = MessageHandlers::new();
+        handlers.register("msg-1", |_: Arc<ConnectionContext>, _: MessageArgs| async {
+            vec![]
+        });
+        state.reload(
+            AgentConfig {
+                listen_addrs: vec!["ipv4@127.0.0.1:6001".parse()?],
+                max_frame_size: 1024,
+                threads: 4,
+                ..Default::default()
+            },
+            handlers,
+        );
+
+        assert_eq!(state.config().max_frame_size, 1024);
+        assert_eq!(
+            state.config().listen_addrs,
+            AgentConfig::default().listen_addrs
+        );
+        assert_eq!(state.config().threads, 1);
+        assert!(state.handlers().get("msg-1").is_some());
+        // Snapshots taken before the reload are left untouched.
+        assert_eq!(config.max_frame_size, AgentConfig::default().max_frame_size);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{
+    Action, ActionVarScope, ConnectionContext, HandlerOptions, MessageArgs, MessageHandler,
+    MessageHandlerFactories, TypedData, VarintString,
+};
+use anyhow::{anyhow, bail};
+use std::str::FromStr;
+use std::sync::Arc;
+use toml::Value;
+
+impl MessageHandlerFactories {
+    // Handlers usable from the config file without writing any code.
+    pub fn builtin() -> Self {
+        let mut factories = Self::new();
+        factories
+            .register("set-var", set_var)
+            .register("copy-args", copy_args);
+        factories
+    }
+}
+
+// Sets one variable to a fixed value, e.g.
+// options = { scope = "txn", name = "var_name_1", value = "var-value-1" }
+fn set_var(options: &HandlerOptions) -> anyhow::Result<Arc<dyn MessageHandler>> {
+    check_option_names(options, &["scope", "name", "value"])?;
+
+    let scope = scope_option(options)?;
+    let name = string_option(options, "name")?.ok_or_else(|| anyhow!("missing option name"))?;
+    let value = match options.get("value") {
+        Some(Value::String(s)) => TypedData::STRING(VarintString::new(s)),
+        Some(Value::Integer(i)) => TypedData::INT64(*i),
+        Some(Value::Boolean(b)) => TypedData::BOOL(*b),
+        Some(_) => bail!("option value must be a string, an integer or a boolean"),
+        None => bail!("missing option value"),
+    };
+
+    let action = Action::set_val(scope, VarintString::new(&name), value);
+    Ok(Arc::new(
+        move |_: Arc<ConnectionContext>, _: MessageArgs| {
+            let action = action.clone();
+            async move { vec![action] }
+        },
+    ))
+}
+
+// Copies every argument of the message into a variable named after it, e.g.
+// options = { scope = "txn", prefix = "arg_" }
+fn copy_args(options: &HandlerOptions) -> anyhow::Result<Arc<dyn MessageHandler>> {
+    check_option_names(options, &["scope", "prefix"])?;
+
+    let scope = scope_option(options)?;
+    let prefix = string_option(options, "prefix")?.unwrap_or_default();
+
+    Ok(Arc::new(
+        move |_: Arc<ConnectionContext>, args: MessageArgs| {
+            let actions: Vec<Action> = args
+                .into_iter()
+                .map(|(name, value)| {
+                    Action::set_val(
+                        scope.clone(),
+                        VarintString::new(&format!("{}{}", prefix, name.val())),
+                        value,
+                    )
+                })
+                .collect();
+            async move { actions }
+        },
+    ))
+}
+
+fn check_option_names(options: &HandlerOptions, names: &[&str]) -> anyhow::Result<()> {
+    match options.keys().find(|x| !names.contains(&x.as_str())) {
+        Some(name) => bail!(
+            "unknown option {}, expected one of {}",
+            name,
+            names.join(", ")
+        ),
+        None => Ok(()),
+    }
+}
+
+fn string_option(options: &HandlerOptions, name: &str) -> anyhow::Result<Option<String>> {
+    match options.get(name) {
+        Some(Value::String(s)) => Ok(Some(s.to_owned())),
+        Some(_) => bail!("option {} must be a string", name),
+        None => Ok(None),
+    }
+}
+
+// Defaults to the transaction scope, like `option var-prefix` variables in SPOE.
+fn scope_option(options: &HandlerOptions) -> anyhow::Result<ActionVarScope> {
+    match string_option(options, "scope")? {
+        Some(scope) => ActionVarScope::from_str(&scope).map_err(|_| {
+            anyhow!(
+                "invalid option scope {}, expected one of proc, sess, txn, req, res",
+                scope
+            )
+        }),
+        None => Ok(ActionVarScope::TRANSACTION),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn options(s: &str) -> HandlerOptions {
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_set_var() -> anyhow::Result<()> {
+        let factories = MessageHandlerFactories::builtin();
+        let factory = factories.get("set-var").unwrap();
+
+        let handler = factory.build(&options(
+            r#"scope = "req"
+name = "var_name_1"
+value = 1"#,
+        ))?;
+        let actions =
+            block_on(handler.handle(Arc::new(ConnectionContext::new()), MessageArgs::new()));
+        assert_eq!(actions.len(), 1);
+        match &actions[0] {
+            Action::SET_VAR {
+                var_scope,
+                var_name,
+                var_value,
+            } => {
+                assert_eq!(var_scope, &ActionVarScope::REQUEST);
+                assert_eq!(var_name.val(), "var_name_1");
+                assert_eq!(var_value, &TypedData::INT64(1));
+            }
+            _ => panic!("should SET_VAR"),
+        }
+
+        assert!(factory.build(&options(r#"name = "var_name_1""#)).is_err());
+        assert!(factory
+            .build(&options(
+                r#"scope = "foo"
+name = "var_name_1"
+value = 1"#
+            ))
+            .is_err());
+        assert!(factory
+            .build(&options(
+                r#"name = "var_name_1"
+value = 1
+foo = 1"#
+            ))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_args() -> anyhow::Result<()> {
+        let factories = MessageHandlerFactories::builtin();
+        let handler = factories
+            .get("copy-args")
+            .unwrap()
+            .build(&options(r#"prefix = "spoe_""#))?;
+
+        let mut args = MessageArgs::new();
+        args.insert(
+            VarintString::new("arg_method"),
+            TypedData::STRING(VarintString::new("GET")),
+        );
+        let actions = block_on(handler.handle(Arc::new(ConnectionContext::new()), args));
+        assert_eq!(actions.len(), 1);
+        match &actions[0] {
+            Action::SET_VAR {
+                var_scope,
+                var_name,
+                var_value,
+            } => {
+                assert_eq!(var_scope, &ActionVarScope::TRANSACTION);
+                assert_eq!(var_name.val(), "spoe_arg_method");
+                assert_eq!(var_value, &TypedData::STRING(VarintString::new("GET")));
+            }
+            _ => panic!("should SET_VAR"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use haproxy_spoa_example::{
+    AgentConfig, ConfigFile, HAProxyHelloFrameCapability, ListenAddr, MessageHandlerFactories,
+    MessageHandlers, SupportVersion, TlsServerConfig,
+};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "HAProxy SPOE agent")]
+pub struct Cli {
+    /// TOML config file, command-line flags take precedence over it
+    #[structopt(short, long, global = true, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Listen address, can be repeated, e.g. unix@haproxy_run/spoa_demo.sock, abns@spoa_demo, ipv4@127.0.0.1:6001, ipv6@[::1]:6001 or fd@<systemd FileDescriptorName>
+    #[structopt(short, long = "listen", global = true, number_of_values = 1)]
+    pub listen_addrs: Vec<ListenAddr>,
+
+    /// SPOP versions advertised in AGENT-HELLO, e.g. 2.0
+    #[structopt(long, global = true, use_delimiter = true)]
+    pub supported_versions: Option<Vec<SupportVersion>>,
+
+    /// Upper bound of the max-frame-size negotiated with HAProxy
+    #[structopt(long, global = true)]
+    pub max_frame_size: Option<u32>,
+
+    /// Upper bound of a reassembled fragmented frame
+    #[structopt(long, global = true)]
+    pub max_fragmented_frame_size: Option<usize>,
+
+    /// Enabled capabilities, e.g. pipelining,async,fragmentation
+    #[structopt(long, global = true, use_delimiter = true)]
+    pub capabilities: Option<Vec<HAProxyHelloFrameCapability>>,
+
+    /// Executor threads serving the connections
+    #[structopt(long, global = true)]
+    pub threads: Option<usize>,
+
+    /// Worker processes serving the same listeners, this process then supervises them
+    #[structopt(long, global = true)]
+    pub workers: Option<usize>,
+
+    /// Bind TCP listeners with SO_REUSEPORT, one listening socket per worker
+    #[structopt(long, global = true)]
+    pub reuse_port: bool,
+
+    /// PEM certificate chain, serves the TCP listeners over TLS along with --tls-key
+    #[structopt(long, global = true, parse(from_os_str), requires = "tls-key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --tls-cert
+    #[structopt(long, global = true, parse(from_os_str), requires = "tls-cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA certificates, requires TLS clients to present a certificate signed by them
+    #[structopt(long, global = true, parse(from_os_str), requires = "tls-cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Log filter in RUST_LOG syntax, e.g. info or haproxy_spoa_example=debug [default: RUST_LOG or info]
+    #[structopt(long, global = true)]
+    pub log_level: Option<String>,
+
+    /// Log format
+    #[structopt(long, global = true, default_value = "text", possible_values = &["text", "json"])]
+    pub log_format: LogFormat,
+
+    /// Validate the configuration and exit, same as the check subcommand
+    #[structopt(long)]
+    pub check: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Run the agent (default)
+    Run,
+    /// Validate the configuration and exit
+    Check,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("invalid log format {}", s)),
+        }
+    }
+}
+
+impl Cli {
+    pub fn is_check(&self) -> bool {
+        self.check || matches!(self.command, Some(Command::Check))
+    }
+
+    pub fn init_logger(&self) {
+        let env = env_logger::Env::default().default_filter_or("info");
+        let mut builder = env_logger::Builder::from_env(env);
+        if let Some(log_level) = &self.log_level {
+            builder.parse_filters(log_level);
+        }
+        if self.log_format == LogFormat::Json {
+            builder.format(|buf, record| {
+                let line = serde_json::json!({
+                    "timestamp": buf.timestamp().to_string(),
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{}", line)
+            });
+        }
+        builder.init();
+    }
+
+    // Defaults, then the config file, then the command-line flags.
+    pub fn load(
+        &self,
+        factories: &MessageHandlerFactories,
+    ) -> anyhow::Result<(AgentConfig, MessageHandlers)> {
+        let mut config = AgentConfig::default();
+        let mut handlers = MessageHandlers::new();
+
+        if let Some(path) = &self.config {
+            let config_file = ConfigFile::from_path(path)?;
+            config_file.apply_to(&mut config)?;
+            handlers = config_file.message_handlers(factories)?;
+        }
+
+        if !self.listen_addrs.is_empty() {
+            config.listen_addrs = self.listen_addrs.clone();
+        }
+        if let Some(supported_versions) = &self.supported_versions {
+            config.supported_versions = supported_versions.clone();
+        }
+        if let Some(max_frame_size) = self.max_frame_size {
+            config.max_frame_size = max_frame_size;
+        }
+        if let Some(max_fragmented_frame_size) = self.max_fragmented_frame_size {
+            config.max_fragmented_frame_size = max_fragmented_frame_size;
+        }
+        if let Some(capabilities) = &self.capabilities {
+            config.capabilities = capabilities.clone();
+        }
+        if let Some(threads) = self.threads {
+            config.threads = threads;
+        }
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+        if self.reuse_port {
+            config.reuse_port = true;
+        }
+
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config.tls = Some(TlsServerConfig::load(
+                cert,
+                key,
+                self.tls_client_ca.as_deref(),
+            )?);
+        }
+
+        config.validate()?;
+
+        Ok((config, handlers))
+    }
+}
@@ -0,0 +1,551 @@
+use crate::{
+    lookup_gid, lookup_uid, parse_mode, AgentConfig, AgentConfigError, HAProxyHelloFrameCapability,
+    HandlerOptions, ListenAddr, MessageHandlerFactories, MessageHandlers, SupportVersion, TlsError,
+    TlsServerConfig,
+};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+use toml::Spanned;
+
+// Every key is optional and overrides the matching `AgentConfig` default, e.g.
+//
+// listen = ["unix@haproxy_run/spoa_demo.sock", "ipv4@127.0.0.1:6001"]
+// supported-versions = ["2.0"]
+// max-frame-size = 16380
+// max-fragmented-frame-size = 1048576
+// capabilities = ["pipelining", "async", "fragmentation"]
+// threads = 4
+// workers = 1
+// reuse-port = false
+//
+// [unix-socket]
+// mode = "660"
+// user = "haproxy"
+// group = "haproxy"
+// allowed-users = ["haproxy"]
+// allowed-groups = ["haproxy"]
+// allowed-pids = []
+//
+// [tls]
+// cert = "/etc/spoa/cert.pem"
+// key = "/etc/spoa/key.pem"
+// client-ca = "/etc/spoa/haproxy-ca.pem"
+//
+// [timeout]
+// hello = "5s"
+// idle = "30s"
+// processing = "3s"
+// shutdown = "10s"
+//
+// [[messages]]
+// name = "msg-1"
+// handler = "set-var"
+// options = { scope = "txn", name = "var_name_1", value = "var-value-1" }
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    pub listen: Option<Spanned<Vec<ListenAddr>>>,
+    pub supported_versions: Option<Spanned<Vec<SupportVersion>>>,
+    pub max_frame_size: Option<Spanned<u32>>,
+    pub max_fragmented_frame_size: Option<Spanned<usize>>,
+    pub capabilities: Option<Vec<HAProxyHelloFrameCapability>>,
+    pub threads: Option<Spanned<usize>>,
+    pub workers: Option<Spanned<usize>>,
+    pub reuse_port: Option<bool>,
+    #[serde(default)]
+    pub unix_socket: UnixSocketConfig,
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub timeout: TimeoutConfig,
+    #[serde(default)]
+    pub messages: Vec<MessageConfig>,
+
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    source: String,
+}
+
+// Applied to the socket files of `unix@` listeners, users and groups are names or ids.
+// The allowed peers are checked on every unix listener with SO_PEERCRED.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct UnixSocketConfig {
+    pub mode: Option<Spanned<String>>,
+    pub user: Option<Spanned<String>>,
+    pub group: Option<Spanned<String>>,
+    #[serde(default)]
+    pub allowed_users: Vec<Spanned<String>>,
+    #[serde(default)]
+    pub allowed_groups: Vec<Spanned<String>>,
+    #[serde(default)]
+    pub allowed_pids: Vec<u32>,
+}
+
+// TLS on the TCP listeners, `client-ca` requires a client certificate signed by it.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Spanned<PathBuf>,
+    pub key: Spanned<PathBuf>,
+    pub client_ca: Option<Spanned<PathBuf>>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutConfig {
+    pub hello: Option<Timeout>,
+    pub idle: Option<Timeout>,
+    pub processing: Option<Timeout>,
+    pub shutdown: Option<Timeout>,
+}
+
+// Same notation as the HAProxy `timeout` keywords, e.g. `50ms`, `3s` or `1m`
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Timeout(pub Duration);
+
+impl FromStr for Timeout {
+    type Err = humantime::DurationError;
+
+    fn from_str(s: &str) -> Result<Self, humantime::DurationError> {
+        humantime::parse_duration(s).map(Self)
+    }
+}
+
+impl_deserialize_from_str!(Timeout);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MessageConfig {
+    // The SPOE message name, e.g. `spoe-message msg-1`
+    pub name: Spanned<String>,
+    // A name registered in `MessageHandlerFactories`.
+    pub handler: Spanned<String>,
+    #[serde(default)]
+    pub options: HandlerOptions,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigFileError {
+    #[error("read {} failed", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+    #[error("{}: {1}", .0.display())]
+    Parse(PathBuf, toml::de::Error),
+    #[error("{}:{line}:{column}: {message}", .path.display())]
+    Invalid {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl ConfigFile {
+    pub fn from_path(path: &Path) -> Result<Self, ConfigFileError> {
+        let source =
+            fs::read_to_string(path).map_err(|e| ConfigFileError::Io(path.to_owned(), e))?;
+
+        Self::parse(path, source)
+    }
+
+    // `path` is only used to report errors.
+    pub fn parse(path: &Path, source: String) -> Result<Self, ConfigFileError> {
+        let mut config_file: Self =
+            toml::from_str(&source).map_err(|e| ConfigFileError::Parse(path.to_owned(), e))?;
+        config_file.path = path.to_owned();
+        config_file.source = source;
+
+        Ok(config_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn apply_to(&self, config: &mut AgentConfig) -> Result<(), ConfigFileError> {
+        if let Some(listen_addrs) = &self.listen {
+            config.listen_addrs = listen_addrs.get_ref().clone();
+        }
+        if let Some(supported_versions) = &self.supported_versions {
+            config.supported_versions = supported_versions.get_ref().clone();
+        }
+        if let Some(max_frame_size) = &self.max_frame_size {
+            config.max_frame_size = *max_frame_size.get_ref();
+        }
+        if let Some(max_fragmented_frame_size) = &self.max_fragmented_frame_size {
+            config.max_fragmented_frame_size = *max_fragmented_frame_size.get_ref();
+        }
+        if let Some(capabilities) = &self.capabilities {
+            config.capabilities = capabilities.clone();
+        }
+        if let Some(threads) = &self.threads {
+            config.threads = *threads.get_ref();
+        }
+        if let Some(workers) = &self.workers {
+            config.workers = *workers.get_ref();
+        }
+        if let Some(reuse_port) = self.reuse_port {
+            config.reuse_port = reuse_port;
+        }
+        if let Some(mode) = &self.unix_socket.mode {
+            config.unix_socket_options.mode =
+                Some(parse_mode(mode.get_ref()).ok_or_else(|| {
+                    self.invalid(
+                        mode.span().start,
+                        format!("invalid mode {}, expected an octal number", mode.get_ref()),
+                    )
+                })?);
+        }
+        if let Some(user) = &self.unix_socket.user {
+            config.unix_socket_options.uid = Some(lookup_uid(user.get_ref()).ok_or_else(|| {
+                self.invalid(
+                    user.span().start,
+                    format!("unknown user {}", user.get_ref()),
+                )
+            })?);
+        }
+        if let Some(group) = &self.unix_socket.group {
+            config.unix_socket_options.gid =
+                Some(lookup_gid(group.get_ref()).ok_or_else(|| {
+                    self.invalid(
+                        group.span().start,
+                        format!("unknown group {}", group.get_ref()),
+                    )
+                })?);
+        }
+        for user in &self.unix_socket.allowed_users {
+            config
+                .allowed_peers
+                .uids
+                .push(lookup_uid(user.get_ref()).ok_or_else(|| {
+                    self.invalid(
+                        user.span().start,
+                        format!("unknown user {}", user.get_ref()),
+                    )
+                })?);
+        }
+        for group in &self.unix_socket.allowed_groups {
+            config
+                .allowed_peers
+                .gids
+                .push(lookup_gid(group.get_ref()).ok_or_else(|| {
+                    self.invalid(
+                        group.span().start,
+                        format!("unknown group {}", group.get_ref()),
+                    )
+                })?);
+        }
+        config
+            .allowed_peers
+            .pids
+            .extend_from_slice(&self.unix_socket.allowed_pids);
+        if let Some(tls) = &self.tls {
+            let client_ca = tls.client_ca.as_ref();
+            let tls_server_config = TlsServerConfig::load(
+                tls.cert.get_ref(),
+                tls.key.get_ref(),
+                client_ca.map(|x| x.get_ref().as_path()),
+            )
+            .map_err(|e| {
+                let start = match (&e, client_ca) {
+                    (TlsError::Io(path, _), _) | (TlsError::NoPrivateKey(path), _)
+                        if path == tls.key.get_ref() =>
+                    {
+                        tls.key.span().start
+                    }
+                    (TlsError::Io(path, _), Some(client_ca)) if path == client_ca.get_ref() => {
+                        client_ca.span().start
+                    }
+                    (TlsError::InvalidClientCa(..), Some(client_ca)) => client_ca.span().start,
+                    _ => tls.cert.span().start,
+                };
+                self.invalid(start, e.to_string())
+            })?;
+            config.tls = Some(tls_server_config);
+        }
+        if let Some(Timeout(hello)) = self.timeout.hello {
+            config.hello_timeout = Some(hello);
+        }
+        if let Some(Timeout(idle)) = self.timeout.idle {
+            config.idle_timeout = Some(idle);
+        }
+        if let Some(Timeout(processing)) = self.timeout.processing {
+            config.processing_timeout = Some(processing);
+        }
+        if let Some(Timeout(shutdown)) = self.timeout.shutdown {
+            config.shutdown_timeout = shutdown;
+        }
+
+        config.validate().map_err(|e| {
+            let start = match &e {
+                AgentConfigError::NoListenAddr | AgentConfigError::DuplicateListenAddr(_) => {
+                    self.listen.as_ref().map(|x| x.span().start)
+                }
+                AgentConfigError::NoThreads => self.threads.as_ref().map(|x| x.span().start),
+                AgentConfigError::NoWorkers => self.workers.as_ref().map(|x| x.span().start),
+                AgentConfigError::NoSupportedVersion => {
+                    self.supported_versions.as_ref().map(|x| x.span().start)
+                }
+                AgentConfigError::MaxFrameSizeTooSmall(_) => {
+                    self.max_frame_size.as_ref().map(|x| x.span().start)
+                }
+                AgentConfigError::MaxFragmentedFrameSizeTooSmall(..) => self
+                    .max_fragmented_frame_size
+                    .as_ref()
+                    .map(|x| x.span().start),
+            };
+            self.invalid(start.unwrap_or(0), e.to_string())
+        })
+    }
+
+    pub fn message_handlers(
+        &self,
+        factories: &MessageHandlerFactories,
+    ) -> Result<MessageHandlers, ConfigFileError> {
+        let mut handlers = MessageHandlers::new();
+
+        for message in &self.messages {
+            let message_name = message.name.get_ref();
+            if handlers.get(message_name).is_some() {
+                return Err(self.invalid(
+                    message.name.span().start,
+                    format!("message {} is configured twice", message_name),
+                ));
+            }
+
+            let handler_name = message.handler.get_ref();
+            let factory = factories.get(handler_name).ok_or_else(|| {
+                self.invalid(
+                    message.handler.span().start,
+                    format!(
+                        "unknown handler {}, expected one of {}",
+                        handler_name,
+                        factories.handler_names().join(", ")
+                    ),
+                )
+            })?;
+            let handler = factory.build(&message.options).map_err(|e| {
+                self.invalid(
+                    message.handler.span().start,
+                    format!(
+                        "handler {} of message {}: {}",
+                        handler_name, message_name, e
+                    ),
+                )
+            })?;
+
+            handlers.insert(message_name, handler);
+        }
+
+        Ok(handlers)
+    }
+
+    fn invalid(&self, offset: usize, message: String) -> ConfigFileError {
+        let before = &self.source[..offset.min(self.source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map(|x| x + 1).unwrap_or(0) + 1;
+
+        ConfigFileError::Invalid {
+            path: self.path.clone(),
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AllowedPeers, UnixSocketOptions};
+
+    fn parse(source: &str) -> Result<ConfigFile, ConfigFileError> {
+        ConfigFile::parse(Path::new("agent.toml"), source.to_owned())
+    }
+
+    #[test]
+    fn test_apply_to() -> anyhow::Result<()> {
+        let config_file = parse(
+            r#"
+listen = ["unix@/var/run/spoa_demo.sock", "ipv4@127.0.0.1:6001"]
+max-frame-size = 1024
+capabilities = ["pipelining"]
+threads = 4
+reuse-port = true
+
+[unix-socket]
+mode = "0660"
+user = "root"
+group = "0"
+allowed-users = ["root", "1000"]
+allowed-pids = [1]
+
+[timeout]
+processing = "50ms"
+shutdown = "1s"
+"#,
+        )?;
+
+        let mut config = AgentConfig::default();
+        config_file.apply_to(&mut config)?;
+        assert_eq!(
+            config.listen_addrs,
+            vec![
+                "unix@/var/run/spoa_demo.sock".parse()?,
+                "ipv4@127.0.0.1:6001".parse()?
+            ]
+        );
+        assert_eq!(config.max_frame_size, 1024);
+        assert_eq!(config.threads, 4);
+        assert_eq!(config.workers, 1);
+        assert!(config.reuse_port);
+        assert_eq!(
+            config.unix_socket_options,
+            UnixSocketOptions {
+                mode: Some(0o660),
+                uid: Some(0),
+                gid: Some(0),
+            }
+        );
+        assert_eq!(
+            config.allowed_peers,
+            AllowedPeers {
+                uids: vec![0, 1000],
+                gids: vec![],
+                pids: vec![1],
+            }
+        );
+        assert_eq!(
+            config.capabilities,
+            vec![HAProxyHelloFrameCapability::pipelining]
+        );
+        assert_eq!(
+            config.supported_versions,
+            AgentConfig::default().supported_versions
+        );
+        assert_eq!(config.processing_timeout, Some(Duration::from_millis(50)));
+        assert_eq!(config.idle_timeout, None);
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_location() -> anyhow::Result<()> {
+        let e = parse("listen = []\nmax-frame-szie = 1024\n").unwrap_err();
+        assert!(e.to_string().contains("line 2, column 1"), "{}", e);
+
+        let e = parse("capabilities = [\"pipelining\", \"foo\"]\n").unwrap_err();
+        assert!(e.to_string().contains("line 1"), "{}", e);
+
+        let config_file = parse("supported-versions = [\"2.0\"]\nmax-frame-size = 100\n")?;
+        let e = config_file
+            .apply_to(&mut AgentConfig::default())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "agent.toml:2:18: max-frame-size 100 is less than 256"
+        );
+
+        let config_file = parse("threads = 0\n")?;
+        let e = config_file
+            .apply_to(&mut AgentConfig::default())
+            .unwrap_err();
+        assert_eq!(e.to_string(), "agent.toml:1:11: threads must be at least 1");
+
+        let config_file = parse("[unix-socket]\nallowed-groups = [\"no-such-group\"]\n")?;
+        let e = config_file
+            .apply_to(&mut AgentConfig::default())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "agent.toml:2:19: unknown group no-such-group"
+        );
+
+        let config_file = parse("[tls]\ncert = \"no-such-cert.pem\"\nkey = \"no-such-key.pem\"\n")?;
+        let e = config_file
+            .apply_to(&mut AgentConfig::default())
+            .unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("agent.toml:2:8: read no-such-cert.pem failed"),
+            "{}",
+            e
+        );
+
+        let config_file = parse("[unix-socket]\nmode = \"rw\"\n")?;
+        let e = config_file
+            .apply_to(&mut AgentConfig::default())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "agent.toml:2:8: invalid mode rw, expected an octal number"
+        );
+
+        let config_file = parse(
+            r#"
+[[messages]]
+name = "msg-1"
+handler = "set-var"
+options = { name = "var_name_1", value = "var-value-1" }
+
+[[messages]]
+name = "msg-2"
+handler = "foo"
+"#,
+        )?;
+        let e = config_file
+            .message_handlers(&MessageHandlerFactories::builtin())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "agent.toml:9:11: unknown handler foo, expected one of copy-args, set-var"
+        );
+
+        let config_file = parse(
+            r#"
+[[messages]]
+name = "msg-1"
+handler = "set-var"
+options = { name = "var_name_1" }
+"#,
+        )?;
+        let e = config_file
+            .message_handlers(&MessageHandlerFactories::builtin())
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "agent.toml:4:11: handler set-var of message msg-1: missing option value"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_handlers() -> anyhow::Result<()> {
+        let config_file = parse(
+            r#"
+[[messages]]
+name = "msg-1"
+handler = "set-var"
+options = { name = "var_name_1", value = "var-value-1" }
+
+[[messages]]
+name = "msg-3"
+handler = "copy-args"
+"#,
+        )?;
+
+        let handlers = config_file.message_handlers(&MessageHandlerFactories::builtin())?;
+        let mut message_names = handlers.message_names();
+        message_names.sort_unstable();
+        assert_eq!(message_names, vec!["msg-1", "msg-3"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,582 @@
+use crate::{
+    ack_frame, Action, AgentState, ConnectionContext, ConnectionState, Frame, FrameCodec,
+    FrameCodecError, FrameFlags, FrameHandleError, FrameHeader, FrameKnownError, FramePayload,
+    FrameType, HAProxyHelloFrameCapability, NotifyFrame, Received, Varint,
+};
+use bytes::{Bytes, BytesMut};
+use log::*;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error;
+
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    // Answered with `Connection::ack` once the messages are handled, see `Frame::dispatch`.
+    NotifyReceived(NotifyFrame),
+    // The last frame is in the output, the transport is closed once it is written.
+    DisconnectRequested,
+}
+
+#[derive(Error, Debug)]
+pub enum ConnectionError {
+    #[error("read frame failed")]
+    ReadFailed(#[from] FrameCodecError),
+    #[error("handle frame failed")]
+    HandleFailed(#[from] FrameHandleError),
+    #[error("write frame failed")]
+    WriteFailed(#[source] FrameCodecError),
+}
+
+// The protocol side of a connection without any I/O: it is fed the bytes read from the
+// transport, the ACK actions and the time, and emits the bytes to write and events. The
+// AGENT-DISCONNECT frame is already in the output when an error is returned.
+#[derive(Debug)]
+pub struct Connection {
+    frame: Frame,
+    codec: FrameCodec,
+    input: BytesMut,
+    output: BytesMut,
+    events: VecDeque<ConnectionEvent>,
+    // NOTIFY frames not acknowledged yet.
+    pending: usize,
+    last_activity: Instant,
+    // Set on shutdown, until then the pending NOTIFY frames are still acknowledged.
+    shutdown_deadline: Option<Instant>,
+}
+
+impl Connection {
+    pub fn new(agent_state: Arc<AgentState>, context: ConnectionContext, now: Instant) -> Self {
+        let frame = Frame::with_context(agent_state, context);
+        let codec = FrameCodec::new(frame.config().max_frame_size);
+        Self {
+            frame,
+            codec,
+            input: BytesMut::new(),
+            output: BytesMut::new(),
+            events: VecDeque::new(),
+            pending: 0,
+            last_activity: now,
+            shutdown_deadline: None,
+        }
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.frame.state()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state() == ConnectionState::Closed
+    }
+
+    // Bytes read from the transport, in any chunks.
+    pub fn handle_input(&mut self, now: Instant, bytes: &[u8]) -> Result<(), ConnectionError> {
+        self.last_activity = now;
+        self.input.extend_from_slice(bytes);
+        self.process_input()
+    }
+
+    pub fn ack(
+        &mut self,
+        now: Instant,
+        stream_id: Varint,
+        frame_id: Varint,
+        actions: Vec<Action>,
+    ) -> Result<(), ConnectionError> {
+        if !self.is_open() {
+            debug!("ack dropped while {}", self.state());
+            return Ok(());
+        }
+
+        self.last_activity = now;
+        self.pending = self.pending.saturating_sub(1);
+        let (frame_header, frame_payload) = ack_frame(stream_id, frame_id, actions);
+        let written = match self.frame.fragment_size() {
+            Some(fragment_size) => {
+                self.write_fragmented(&frame_header, &frame_payload, fragment_size as usize)
+            }
+            None => self.write(&frame_header, &frame_payload),
+        };
+        if let Err(e) = written {
+            self.disconnect(FrameKnownError::frame_is_too_big);
+            return Err(ConnectionError::WriteFailed(e));
+        }
+
+        if self.shutdown_deadline.is_some() {
+            if self.pending == 0 {
+                self.disconnect(FrameKnownError::normal);
+            }
+            return Ok(());
+        }
+
+        // Without pipelining, the frames read meanwhile are held back until now.
+        self.process_input()
+    }
+
+    // When `handle_timeout` has to be called next.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if !self.is_open() {
+            return None;
+        }
+        if self.shutdown_deadline.is_some() {
+            return self.shutdown_deadline;
+        }
+
+        let config = self.frame.config();
+        match self.state() {
+            ConnectionState::Connecting => config.hello_timeout,
+            _ if self.pending == 0 => config.idle_timeout,
+            _ => None,
+        }
+        .map(|timeout| self.last_activity + timeout)
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        match self.poll_timeout() {
+            Some(deadline) if deadline <= now => {}
+            _ => return,
+        }
+
+        if self.shutdown_deadline.is_some() {
+            warn!("{} NOTIFY frames dropped on shutdown", self.pending);
+            self.disconnect(FrameKnownError::normal);
+        } else {
+            info!("connection timed out while {}", self.state());
+            self.disconnect(FrameKnownError::timeout);
+        }
+    }
+
+    // Stops reading frames, then disconnects once the pending NOTIFY frames are
+    // acknowledged, up to the shutdown timeout.
+    pub fn shutdown(&mut self, now: Instant) {
+        if !self.is_open() || self.shutdown_deadline.is_some() {
+            return;
+        }
+
+        info!("shutting down while {}", self.state());
+        if self.pending == 0 {
+            self.disconnect(FrameKnownError::normal);
+        } else {
+            self.shutdown_deadline = Some(now + self.frame.config().shutdown_timeout);
+        }
+    }
+
+    // Called once the transport is closed, by either side.
+    pub fn closed(&mut self) {
+        self.frame.closed();
+    }
+
+    pub fn poll_transmit(&mut self) -> Option<Bytes> {
+        if self.output.is_empty() {
+            return None;
+        }
+        Some(self.output.split().freeze())
+    }
+
+    pub fn poll_event(&mut self) -> Option<ConnectionEvent> {
+        self.events.pop_front()
+    }
+
+    fn is_open(&self) -> bool {
+        matches!(
+            self.state(),
+            ConnectionState::Connecting | ConnectionState::Ready
+        )
+    }
+
+    fn process_input(&mut self) -> Result<(), ConnectionError> {
+        while self.is_open() && self.shutdown_deadline.is_none() {
+            // Without pipelining, frames are processed strictly in order.
+            if self.pending > 0
+                && !self
+                    .frame
+                    .context()
+                    .has_capability(&HAProxyHelloFrameCapability::pipelining)
+            {
+                break;
+            }
+
+            let mut bytes = match self.codec.decode_frame(&mut self.input) {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("on read {}", e);
+                    self.disconnect(FrameKnownError::frame_is_too_big);
+                    return Err(e.into());
+                }
+            };
+            debug!("read len: {} bytes: {:?}", bytes.len(), bytes);
+
+            let (received, do_close) = match self.frame.receive(&mut bytes) {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("on handle {:?}", e);
+                    self.disconnect(FrameKnownError::from(&e));
+                    return Err(e.into());
+                }
+            };
+
+            if let Some(max_frame_size) = self.frame.context().max_frame_size {
+                self.codec.set_max_frame_size(max_frame_size);
+            }
+
+            match received {
+                Some(Received::Reply(frame_header, frame_payload)) => self
+                    .write(&frame_header, &frame_payload)
+                    .map_err(ConnectionError::WriteFailed)?,
+                Some(Received::Notify(notify_frame)) => {
+                    self.pending += 1;
+                    self.events
+                        .push_back(ConnectionEvent::NotifyReceived(notify_frame));
+                }
+                None => {}
+            }
+
+            if do_close {
+                self.close();
+            }
+        }
+
+        Ok(())
+    }
+
+    // Straight into the output, without building the frame on its own first.
+    fn write(
+        &mut self,
+        frame_header: &FrameHeader,
+        frame_payload: &FramePayload,
+    ) -> Result<(), FrameCodecError> {
+        let len = self.codec.encode_frame_with(&mut self.output, |buf| {
+            frame_header.write_to(buf);
+            frame_payload.write_to(buf);
+        })?;
+        debug!("write len: {}", len);
+        Ok(())
+    }
+
+    // https://github.com/haproxy/haproxy/blob/v2.1.0/doc/SPOE.txt#L749
+    // The payload is encoded once past the end of the output, then written back in frames of
+    // at most `fragment_size` bytes. The first frame keeps its type, the following ones are
+    // UNSET frames with the same STREAM-ID and FRAME-ID, and only the last one has the FIN bit.
+    fn write_fragmented(
+        &mut self,
+        frame_header: &FrameHeader,
+        frame_payload: &FramePayload,
+        fragment_size: usize,
+    ) -> Result<(), FrameCodecError> {
+        let start = self.output.len();
+        frame_payload.write_to(&mut self.output);
+        let mut payload = self.output.split_off(start);
+
+        // Every fragment header has the same length, FrameTooBig if it leaves no room.
+        let chunk_len = fragment_size
+            .saturating_sub(frame_header.encoded_len())
+            .max(1);
+        let mut r#type = frame_header.r#type.clone();
+        loop {
+            let chunk = payload.split_to(chunk_len.min(payload.len()));
+            let fragment_header = FrameHeader {
+                r#type,
+                flags: FrameFlags::new(payload.is_empty(), false),
+                stream_id: frame_header.stream_id.clone(),
+                frame_id: frame_header.frame_id.clone(),
+            };
+            let len = self.codec.encode_frame_with(&mut self.output, |buf| {
+                fragment_header.write_to(buf);
+                buf.extend_from_slice(&chunk);
+            })?;
+            debug!("write len: {}", len);
+
+            if payload.is_empty() {
+                return Ok(());
+            }
+            r#type = FrameType::UNSET;
+        }
+    }
+
+    fn disconnect(&mut self, frame_known_error: FrameKnownError) {
+        let (frame_header, frame_payload) = self.frame.disconnect_frame(frame_known_error);
+        if let Err(e) = self.write(&frame_header, &frame_payload) {
+            error!("on send {:?}", e);
+        }
+        self.close();
+    }
+
+    fn close(&mut self) {
+        info!("do close");
+        self.events.push_back(ConnectionEvent::DisconnectRequested);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ActionVarScope, AgentConfig, FrameType, MessageArgs, MessageHandlers, TypedData,
+        VarintString, MIN_FRAME_SIZE,
+    };
+    use futures::executor::block_on;
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    const HELLO: &[u8] = b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x10pipelining,async\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57";
+    const NOTIFY: &[u8] =
+        b"\x03\0\0\0\x01\0\x01\x04demo\x02\narg_method\x08\x03GET\x08arg_path\x08\x01/";
+
+    fn connection(config: AgentConfig, now: Instant) -> Connection {
+        let mut handlers = MessageHandlers::new();
+        handlers.register(
+            "demo",
+            |_: Arc<ConnectionContext>, args: MessageArgs| async move {
+                vec![Action::set_val(
+                    ActionVarScope::TRANSACTION,
+                    VarintString::new("method"),
+                    args.get(&VarintString::new("arg_method")).unwrap().clone(),
+                )]
+            },
+        );
+        let agent_state = Arc::new(AgentState::new(config, handlers));
+        Connection::new(agent_state, ConnectionContext::new(), now)
+    }
+
+    fn input(frame: &[u8]) -> Vec<u8> {
+        let mut buf = (frame.len() as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(frame);
+        buf
+    }
+
+    fn output(connection: &mut Connection) -> anyhow::Result<Vec<(FrameHeader, FramePayload)>> {
+        let mut frames = vec![];
+        let mut bytes = BytesMut::from(&connection.poll_transmit().unwrap_or_default()[..]);
+        while let Some(mut bytes) = FrameCodec::default().decode_frame(&mut bytes)? {
+            let frame_header: FrameHeader = (&mut bytes).try_into()?;
+            let frame_payload: FramePayload = (&mut bytes, &frame_header.r#type).try_into()?;
+            frames.push((frame_header, frame_payload));
+        }
+        Ok(frames)
+    }
+
+    #[test]
+    fn test_handle_input() -> anyhow::Result<()> {
+        let now = Instant::now();
+        let mut connection = connection(Default::default(), now);
+
+        // Split anywhere, even within the length.
+        let mut bytes = input(HELLO);
+        bytes.extend(input(NOTIFY));
+        connection.handle_input(now, &bytes[..2])?;
+        assert!(connection.poll_transmit().is_none());
+        connection.handle_input(now, &bytes[2..])?;
+
+        let frames = output(&mut connection)?;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0.r#type, FrameType::AGENT_HELLO);
+        assert_eq!(connection.state(), ConnectionState::Ready);
+
+        let notify_frame = match connection.poll_event() {
+            Some(ConnectionEvent::NotifyReceived(notify_frame)) => notify_frame,
+            _ => panic!("should receive NOTIFY"),
+        };
+        assert!(connection.poll_event().is_none());
+        // No idle timeout while a NOTIFY frame is pending.
+        assert_eq!(connection.poll_timeout(), None);
+
+        let actions = block_on(connection.frame().dispatch(notify_frame.payload.messages));
+        connection.ack(now, notify_frame.stream_id, notify_frame.frame_id, actions)?;
+
+        let frames = output(&mut connection)?;
+        assert_eq!(frames[0].0.r#type, FrameType::ACK);
+        assert_eq!(frames[0].0.frame_id.u64_val(), 1);
+        match &frames[0].1.get_list_of_actions().unwrap()[0] {
+            Action::SET_VAR { var_value, .. } => {
+                assert_eq!(var_value, &TypedData::STRING(VarintString::new("GET")))
+            }
+            _ => panic!("should SET_VAR"),
+        }
+
+        connection.handle_input(
+            now,
+            &input(b"\x02\0\0\0\x01\0\0\x0bstatus-code\x03\x00\x07message\x08\x02ok"),
+        )?;
+        let frames = output(&mut connection)?;
+        assert_eq!(frames[0].0.r#type, FrameType::AGENT_DISCONNECT);
+        assert!(matches!(
+            connection.poll_event(),
+            Some(ConnectionEvent::DisconnectRequested)
+        ));
+        assert_eq!(connection.state(), ConnectionState::Disconnecting);
+
+        connection.closed();
+        assert!(connection.is_closed());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ack_fragmented() -> anyhow::Result<()> {
+        let config = AgentConfig {
+            max_frame_size: MIN_FRAME_SIZE,
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut connection = connection(config, now);
+
+        connection.handle_input(now, &input(b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x1epipelining,async,fragmentation\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57"))?;
+        connection.poll_transmit();
+
+        let actions = vec![Action::set_val(
+            ActionVarScope::TRANSACTION,
+            VarintString::new("body"),
+            TypedData::STRING(VarintString::new(&"x".repeat(600))),
+        )];
+        connection.ack(now, Varint::from(5u64), Varint::from(7u64), actions)?;
+
+        let mut bytes = BytesMut::from(&connection.poll_transmit().unwrap()[..]);
+        let mut types = vec![];
+        let mut payload = BytesMut::new();
+        while let Some(mut bytes) = FrameCodec::default().decode_frame(&mut bytes)? {
+            assert!(bytes.len() <= MIN_FRAME_SIZE as usize);
+            let frame_header: FrameHeader = (&mut bytes).try_into()?;
+            assert_eq!(frame_header.stream_id.u64_val(), 5);
+            assert_eq!(frame_header.frame_id.u64_val(), 7);
+            types.push((frame_header.r#type, frame_header.flags.is_fin()));
+            payload.extend_from_slice(&bytes);
+        }
+        assert_eq!(
+            types,
+            vec![
+                (FrameType::ACK, false),
+                (FrameType::UNSET, false),
+                (FrameType::UNSET, true)
+            ]
+        );
+        let frame_payload: FramePayload = (&mut payload.freeze(), &FrameType::ACK).try_into()?;
+        assert_eq!(frame_payload.get_list_of_actions().unwrap().len(), 1);
+        assert!(connection.poll_event().is_none());
+
+        // Frames that fit are not fragmented.
+        connection.ack(now, Varint::from(5u64), Varint::from(8u64), vec![])?;
+        let frames = output(&mut connection)?;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0.r#type, FrameType::ACK);
+        assert!(frames[0].0.flags.is_fin());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_input_error() -> anyhow::Result<()> {
+        let now = Instant::now();
+        let mut connection = connection(Default::default(), now);
+
+        match connection.handle_input(now, &input(NOTIFY)) {
+            Err(ConnectionError::HandleFailed(FrameHandleError::InvalidState(..))) => {}
+            _ => panic!("should err"),
+        }
+        let frames = output(&mut connection)?;
+        assert_eq!(frames[0].0.r#type, FrameType::AGENT_DISCONNECT);
+        assert_eq!(
+            frames[0].1.get_kv_list_value("status-code"),
+            Some(&TypedData::UINT32(
+                FrameKnownError::invalid_frame_received as u32
+            ))
+        );
+        assert!(matches!(
+            connection.poll_event(),
+            Some(ConnectionEvent::DisconnectRequested)
+        ));
+
+        // Nothing is read past the disconnect.
+        connection.handle_input(now, &input(HELLO))?;
+        assert!(connection.poll_transmit().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_timeout() -> anyhow::Result<()> {
+        let config = AgentConfig {
+            hello_timeout: Some(Duration::from_secs(5)),
+            idle_timeout: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut connection = connection(config, now);
+        assert_eq!(
+            connection.poll_timeout(),
+            Some(now + Duration::from_secs(5))
+        );
+
+        let now = now + Duration::from_secs(1);
+        connection.handle_input(now, &input(HELLO))?;
+        assert_eq!(
+            connection.poll_timeout(),
+            Some(now + Duration::from_secs(30))
+        );
+
+        connection.handle_timeout(now + Duration::from_secs(29));
+        assert!(connection.poll_event().is_none());
+
+        connection.poll_transmit();
+        connection.handle_timeout(now + Duration::from_secs(30));
+        let frames = output(&mut connection)?;
+        assert_eq!(
+            frames[0].1.get_kv_list_value("status-code"),
+            Some(&TypedData::UINT32(FrameKnownError::timeout as u32))
+        );
+        assert!(matches!(
+            connection.poll_event(),
+            Some(ConnectionEvent::DisconnectRequested)
+        ));
+        assert_eq!(connection.poll_timeout(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown() -> anyhow::Result<()> {
+        let config = AgentConfig {
+            shutdown_timeout: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut connection = connection(config, now);
+
+        connection.handle_input(now, &input(HELLO))?;
+        connection.handle_input(now, &input(NOTIFY))?;
+        connection.poll_transmit();
+        let notify_frame = match connection.poll_event() {
+            Some(ConnectionEvent::NotifyReceived(notify_frame)) => notify_frame,
+            _ => panic!("should receive NOTIFY"),
+        };
+
+        connection.shutdown(now);
+        assert_eq!(
+            connection.poll_timeout(),
+            Some(now + Duration::from_secs(10))
+        );
+        // Frames read during the shutdown are not handled.
+        connection.handle_input(now, &input(NOTIFY))?;
+        assert!(connection.poll_event().is_none());
+
+        connection.ack(now, notify_frame.stream_id, notify_frame.frame_id, vec![])?;
+        let frames = output(&mut connection)?;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].0.r#type, FrameType::ACK);
+        assert_eq!(frames[1].0.r#type, FrameType::AGENT_DISCONNECT);
+        assert_eq!(
+            frames[1].1.get_kv_list_value("status-code"),
+            Some(&TypedData::UINT32(FrameKnownError::normal as u32))
+        );
+        assert!(matches!(
+            connection.poll_event(),
+            Some(ConnectionEvent::DisconnectRequested)
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{HAProxyHelloFrameCapability, SupportVersion};
+
+#[derive(Clone, Default, Debug)]
+pub struct ConnectionContext {
+    pub engine_id: Option<String>,
+    pub version: Option<SupportVersion>,
+    pub max_frame_size: Option<u32>,
+    // Negotiated in HAPROXY-HELLO/AGENT-HELLO, empty until the handshake is done.
+    pub capabilities: Vec<HAProxyHelloFrameCapability>,
+    // Subject of the client certificate on TLS listeners with a client CA configured.
+    pub client_subject: Option<String>,
+}
+
+impl ConnectionContext {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn has_capability(&self, capability: &HAProxyHelloFrameCapability) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
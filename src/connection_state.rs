@@ -0,0 +1,54 @@
+use crate::FrameType;
+
+// A connection starts with the HAPROXY-HELLO/AGENT-HELLO handshake, then carries
+// NOTIFY/ACK frames until one of the peers sends a DISCONNECT frame.
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug, Display)]
+pub enum ConnectionState {
+    #[default]
+    Connecting,
+    Ready,
+    Disconnecting,
+    Closed,
+}
+
+impl ConnectionState {
+    // Frames HAProxy may send in this state, anything else is an invalid frame.
+    pub fn accepts(&self, frame_type: &FrameType) -> bool {
+        match self {
+            Self::Connecting => matches!(
+                frame_type,
+                FrameType::HAPROXY_HELLO | FrameType::HAPROXY_DISCONNECT
+            ),
+            Self::Ready => matches!(
+                frame_type,
+                FrameType::NOTIFY | FrameType::UNSET | FrameType::HAPROXY_DISCONNECT
+            ),
+            Self::Disconnecting | Self::Closed => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() -> anyhow::Result<()> {
+        let state = ConnectionState::default();
+        assert_eq!(state, ConnectionState::Connecting);
+        assert!(state.accepts(&FrameType::HAPROXY_HELLO));
+        assert!(!state.accepts(&FrameType::NOTIFY));
+        assert!(!state.accepts(&FrameType::UNSET));
+
+        let state = ConnectionState::Ready;
+        assert!(!state.accepts(&FrameType::HAPROXY_HELLO));
+        assert!(state.accepts(&FrameType::NOTIFY));
+        assert!(state.accepts(&FrameType::HAPROXY_DISCONNECT));
+        assert!(!state.accepts(&FrameType::ACK));
+
+        assert!(!ConnectionState::Disconnecting.accepts(&FrameType::NOTIFY));
+        assert!(!ConnectionState::Closed.accepts(&FrameType::HAPROXY_DISCONNECT));
+
+        Ok(())
+    }
+}
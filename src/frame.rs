@@ -1,28 +1,92 @@
-use crate::Varint;
 use crate::{
-    AckFrame, AckFramePayload, Action, ActionVarScope, AgentDisconnectFrame,
-    AgentDisconnectFramePayload, AgentHelloFrame, AgentHelloFramePayload, FrameHeader,
-    FrameHeaderParseError, FrameKnownError, FramePayload, FramePayloadParseError, FrameType,
-    HAProxyDisconnectFrame, HAProxyHelloFrame, HAProxyHelloFrameCapability, NotifyFrame,
-    SupportVersion, TypedData, VarintString,
+    AckFrame, AckFramePayload, Action, AgentConfig, AgentDisconnectFrame,
+    AgentDisconnectFramePayload, AgentHelloFrame, AgentHelloFramePayload, AgentState,
+    ConnectionContext, ConnectionState, FrameHeader, FrameHeaderParseError, FrameKnownError,
+    FramePayload, FramePayloadParseError, FrameReassembler, FrameReassemblyError, FrameType,
+    HAProxyDisconnectFrame, HAProxyDisconnectFrameParseError, HAProxyHelloFrame,
+    HAProxyHelloFrameCapability, HAProxyHelloFrameParseError, MessageArgs, NotifyFrame,
+    NotifyFrameParseError, Reassembled, Varint, VarintString,
 };
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
+use futures::future::{join_all, select, BoxFuture, Either, FutureExt};
+use futures_timer::Delay;
 use log::*;
-use semver::Version;
-use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug)]
 pub struct Frame {
-    hash: HashMap<(Varint, Varint), (FrameType, BytesMut)>,
+    state: ConnectionState,
+    reassembler: FrameReassembler,
+    config: Arc<AgentConfig>,
+    agent_state: Arc<AgentState>,
+    context: Arc<ConnectionContext>,
 }
 impl Frame {
-    pub fn new() -> Self {
+    // The config is fixed for the connection, message handlers are looked up per NOTIFY.
+    pub fn new(agent_state: Arc<AgentState>) -> Self {
+        Self::with_context(agent_state, ConnectionContext::new())
+    }
+
+    // `context` carries what is known before HAPROXY-HELLO, e.g. the TLS client subject.
+    pub fn with_context(agent_state: Arc<AgentState>, context: ConnectionContext) -> Self {
+        let config = agent_state.config();
         Self {
-            hash: Default::default(),
+            state: ConnectionState::default(),
+            reassembler: FrameReassembler::new(config.max_fragmented_frame_size),
+            config,
+            agent_state,
+            context: Arc::new(context),
         }
     }
+
+    pub fn config(&self) -> &AgentConfig {
+        &self.config
+    }
+
+    pub fn context(&self) -> &ConnectionContext {
+        &self.context
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        info!("connection state: {} -> {}", self.state, state);
+        self.state = state;
+    }
+
+    // Called once the connection is closed, no frame is accepted afterwards.
+    pub fn closed(&mut self) {
+        self.set_state(ConnectionState::Closed);
+    }
+
+    pub fn disconnect_frame(
+        &mut self,
+        frame_known_error: FrameKnownError,
+    ) -> (FrameHeader, FramePayload) {
+        self.set_state(ConnectionState::Disconnecting);
+
+        let frame = AgentDisconnectFrame::new(AgentDisconnectFramePayload::from_frame_known_error(
+            frame_known_error,
+        ));
+        frame.into()
+    }
+}
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+// NOTIFY frames are answered once their handlers complete, in whatever order they finish.
+#[derive(Debug)]
+pub enum Received {
+    Reply(FrameHeader, FramePayload),
+    Notify(NotifyFrame),
 }
 
 #[derive(Error, Debug)]
@@ -31,50 +95,77 @@ pub enum FrameHandleError {
     ToFrameHeaderFailed(#[from] FrameHeaderParseError),
     #[error("to FramePayload failed")]
     ToFramePayloadFailed(#[from] FramePayloadParseError),
+    #[error("reassemble frame failed")]
+    ReassembleFailed(#[from] FrameReassemblyError),
+    #[error("to HAProxyHelloFrame failed")]
+    ToHAProxyHelloFrameFailed(#[from] HAProxyHelloFrameParseError),
+    #[error("to HAProxyDisconnectFrame failed")]
+    ToHAProxyDisconnectFrameFailed(#[from] HAProxyDisconnectFrameParseError),
+    #[error("to NotifyFrame failed")]
+    ToNotifyFrameFailed(#[from] NotifyFrameParseError),
+    #[error("fragmented frame received but fragmentation was not negotiated")]
+    FragmentationNotSupported,
+    #[error("no supported version in common")]
+    UnsupportedVersion,
+    #[error("invalid max-frame-size {0}")]
+    InvalidMaxFrameSize(u32),
+    #[error("unexpected frame type {0:?}")]
+    UnexpectedFrameType(FrameType),
+    #[error("frame type {0:?} not allowed while {1}")]
+    InvalidState(FrameType, ConnectionState),
+}
+
+// The status code sent in the AGENT-DISCONNECT frame before the connection is closed.
+impl From<&FrameHandleError> for FrameKnownError {
+    fn from(e: &FrameHandleError) -> Self {
+        match e {
+            FrameHandleError::ReassembleFailed(e) => e.into(),
+            FrameHandleError::ToHAProxyHelloFrameFailed(e) => e.into(),
+            FrameHandleError::FragmentationNotSupported => {
+                Self::payload_fragmentation_is_not_supported
+            }
+            FrameHandleError::UnsupportedVersion => Self::unsupported_version,
+            FrameHandleError::InvalidMaxFrameSize(_) => Self::max_frame_size_too_big_or_too_small,
+            _ => Self::invalid_frame_received,
+        }
+    }
 }
 
 impl Frame {
-    pub fn handle(
+    // HAPROXY-HELLO and HAPROXY-DISCONNECT are answered right away, NOTIFY frames are left
+    // to `dispatch` and `ack_frame`, see `Connection`.
+    pub fn receive(
         &mut self,
         bytes: &mut Bytes,
-    ) -> Result<(Option<BytesMut>, bool), FrameHandleError> {
+    ) -> Result<(Option<Received>, bool), FrameHandleError> {
         let frame_header: FrameHeader = bytes.try_into()?;
         debug!("read frame_header: {:?}", frame_header);
 
-        let key = (
-            frame_header.stream_id.clone(),
-            frame_header.frame_id.clone(),
-        );
-
-        if !frame_header.flags.is_fin() {
-            if frame_header.stream_id.u64_val() == 0 && frame_header.frame_id.u64_val() == 0 {
-                panic!("should close")
-            }
+        if !self.state.accepts(&frame_header.r#type) {
+            return Err(FrameHandleError::InvalidState(
+                frame_header.r#type,
+                self.state,
+            ));
+        }
 
-            match &frame_header.r#type {
-                FrameType::NOTIFY | FrameType::UNSET => {
-                    if let Some((_, buf)) = self.hash.get_mut(&key) {
-                        buf.extend_from_slice(&bytes[..]);
-                    } else {
-                        self.hash
-                            .insert(key, (frame_header.r#type, BytesMut::from(&bytes[..])));
-                    }
+        if !frame_header.flags.is_fin()
+            && !self
+                .context
+                .has_capability(&HAProxyHelloFrameCapability::fragmentation)
+        {
+            return Err(FrameHandleError::FragmentationNotSupported);
+        }
 
+        let (frame_header, mut bytes) =
+            match self.reassembler.push(frame_header, bytes.split_off(0))? {
+                Reassembled::Complete(frame_header, bytes) => (frame_header, bytes),
+                Reassembled::Incomplete => return Ok((None, false)),
+                Reassembled::Aborted(frame_header) => {
+                    info!("frame aborted, frame_header: {:?}", frame_header);
                     return Ok((None, false));
                 }
-                _ => panic!("not support"),
-            }
-        }
-
-        let (frame_type, mut bytes) = if let Some((frame_type, buf_part)) = self.hash.remove(&key) {
-            let mut buf = BytesMut::new();
-            buf.extend_from_slice(&buf_part[..]);
-            buf.extend_from_slice(&bytes[..]);
-
-            (frame_type.clone(), buf.freeze())
-        } else {
-            (frame_header.clone().r#type.to_owned(), bytes.slice(..))
-        };
+            };
+        let frame_type = frame_header.r#type.clone();
         let bytes = &mut bytes;
 
         let frame_payload: FramePayload = (bytes, &frame_type).try_into()?;
@@ -84,106 +175,392 @@ impl Frame {
 
         let (frame_header_out, frame_payload_out): (FrameHeader, FramePayload) = match &frame_type {
             FrameType::HAPROXY_HELLO => {
-                match HAProxyHelloFrame::try_from((frame_header, frame_payload)) {
-                    Ok(haproxy_hello_frame) => {
-                        let frame = AgentHelloFrame::new(AgentHelloFramePayload::new(
-                            SupportVersion::new(Version::new(2, 0, 0)),
-                            haproxy_hello_frame.payload.max_frame_size,
-                            vec![
-                                HAProxyHelloFrameCapability::r#async,
-                                HAProxyHelloFrameCapability::pipelining,
-                                HAProxyHelloFrameCapability::fragmentation,
-                            ],
-                        ));
-                        if haproxy_hello_frame.payload.healthcheck == Some(true) {
-                            do_close = true
-                        }
-                        frame.into()
-                    }
-                    Err(e) => {
-                        error!("make HAProxyHelloFrame failed, error: {}", e);
-
-                        let frame = AgentDisconnectFrame::new(
-                            AgentDisconnectFramePayload::from_frame_known_error(
-                                FrameKnownError::invalid_frame_received,
-                            ),
-                        );
-
-                        do_close = true;
-                        frame.into()
-                    }
+                let haproxy_hello_frame =
+                    HAProxyHelloFrame::try_from((frame_header, frame_payload))?;
+
+                let version = self
+                    .config
+                    .negotiate_version(&haproxy_hello_frame.payload.supported_versions)
+                    .ok_or(FrameHandleError::UnsupportedVersion)?;
+
+                let max_frame_size = self
+                    .config
+                    .negotiate_max_frame_size(haproxy_hello_frame.payload.max_frame_size)
+                    .ok_or(FrameHandleError::InvalidMaxFrameSize(
+                        haproxy_hello_frame.payload.max_frame_size,
+                    ))?;
+
+                let capabilities = self
+                    .config
+                    .negotiate_capabilities(&haproxy_hello_frame.payload.capabilities);
+
+                let frame = AgentHelloFrame::new(AgentHelloFramePayload::new(
+                    version.clone(),
+                    max_frame_size,
+                    capabilities.clone(),
+                ));
+                // Healthcheck connections are closed right after AGENT-HELLO.
+                if haproxy_hello_frame.payload.healthcheck == Some(true) {
+                    do_close = true;
+                    self.set_state(ConnectionState::Disconnecting);
+                } else {
+                    self.set_state(ConnectionState::Ready);
                 }
+
+                let context = Arc::make_mut(&mut self.context);
+                context.engine_id = haproxy_hello_frame.payload.engine_id;
+                context.version = Some(version);
+                context.max_frame_size = Some(max_frame_size);
+                context.capabilities = capabilities;
+                frame.into()
             }
             FrameType::HAPROXY_DISCONNECT => {
-                match HAProxyDisconnectFrame::try_from((frame_header, frame_payload)) {
-                    Ok(haproxy_disconnect_frame) => {
-                        let frame = AgentDisconnectFrame::new(AgentDisconnectFramePayload::new(
-                            haproxy_disconnect_frame.payload.status_code,
-                            haproxy_disconnect_frame.payload.message,
-                        ));
-                        do_close = true;
-                        frame.into()
-                    }
-                    Err(e) => {
-                        error!("make HAProxyDisconnectFrame failed, error: {}", e);
-
-                        let frame = AgentDisconnectFrame::new(
-                            AgentDisconnectFramePayload::from_frame_known_error(
-                                FrameKnownError::invalid_frame_received,
-                            ),
-                        );
-                        do_close = true;
-                        frame.into()
-                    }
-                }
+                let haproxy_disconnect_frame =
+                    HAProxyDisconnectFrame::try_from((frame_header, frame_payload))?;
+
+                let frame = AgentDisconnectFrame::new(AgentDisconnectFramePayload::new(
+                    haproxy_disconnect_frame.payload.status_code,
+                    haproxy_disconnect_frame.payload.message,
+                ));
+                do_close = true;
+                self.set_state(ConnectionState::Disconnecting);
+                frame.into()
             }
-            FrameType::NOTIFY => match NotifyFrame::try_from((frame_header, frame_payload)) {
-                Ok(notify_frame) => {
-                    let mut actions: Vec<Action> = vec![];
-                    if let Some(_) = notify_frame
-                        .payload
-                        .messages
-                        .get(&VarintString::new("msg-1"))
-                    {
-                        actions.push(Action::set_val(
-                            ActionVarScope::TRANSACTION,
-                            VarintString::new("var_name_1"),
-                            TypedData::STRING(VarintString::new("var-value-1")),
-                        ))
-                    }
-
-                    let frame = AckFrame::new(
-                        notify_frame.stream_id,
-                        notify_frame.frame_id,
-                        AckFramePayload::new(actions),
-                    );
-
-                    frame.into()
-                }
-                Err(e) => {
-                    error!("make NotifyFrame failed, error: {}", e);
+            FrameType::NOTIFY => {
+                let notify_frame = NotifyFrame::try_from((frame_header, frame_payload))?;
+                return Ok((Some(Received::Notify(notify_frame)), false));
+            }
+            frame_type => return Err(FrameHandleError::UnexpectedFrameType(frame_type.clone())),
+        };
 
-                    let frame = AgentDisconnectFrame::new(
-                        AgentDisconnectFramePayload::from_frame_known_error(
-                            FrameKnownError::invalid_frame_received,
-                        ),
-                    );
+        info!(
+            "write frame_header: {:?}, frame_payload: {:?}, do_close: {}",
+            frame_header_out, frame_payload_out, do_close
+        );
 
-                    do_close = true;
-                    frame.into()
+        Ok((
+            Some(Received::Reply(frame_header_out, frame_payload_out)),
+            do_close,
+        ))
+    }
+
+    // Runs the handler of every message, the actions are sent back with `ack_frame`.
+    pub fn dispatch(
+        &self,
+        messages: Vec<(VarintString, MessageArgs)>,
+    ) -> BoxFuture<'static, Vec<Action>> {
+        let handlers = self.agent_state.handlers();
+        let mut futures = vec![];
+        for (message_name, args) in messages.into_iter() {
+            match handlers.get(message_name.val()) {
+                Some(handler) => {
+                    let fut = handler.handle(self.context.clone(), args);
+                    futures.push(match self.config.processing_timeout {
+                        Some(timeout) => {
+                            with_processing_timeout(fut, timeout, message_name.val().to_owned())
+                        }
+                        None => fut,
+                    });
+                }
+                None => {
+                    debug!("no handler for message: {}", message_name.val());
                 }
+            }
+        }
+
+        async move { join_all(futures).await.into_iter().flatten().collect() }.boxed()
+    }
+
+    // The max-frame-size once fragmentation is negotiated.
+    pub fn fragment_size(&self) -> Option<u32> {
+        if self
+            .context
+            .has_capability(&HAProxyHelloFrameCapability::fragmentation)
+        {
+            self.context.max_frame_size
+        } else {
+            None
+        }
+    }
+}
+
+// Fragmented by `Connection` past `Frame::fragment_size`.
+pub fn ack_frame(
+    stream_id: Varint,
+    frame_id: Varint,
+    actions: Vec<Action>,
+) -> (FrameHeader, FramePayload) {
+    let frame = AckFrame::new(stream_id, frame_id, AckFramePayload::new(actions));
+
+    let (frame_header_out, frame_payload_out) = frame.into();
+    debug!(
+        "write frame_header: {:?}, frame_payload: {:?}",
+        frame_header_out, frame_payload_out
+    );
+
+    (frame_header_out, frame_payload_out)
+}
+
+// The message is acknowledged without its actions, like HAProxy does past `timeout processing`.
+fn with_processing_timeout(
+    fut: BoxFuture<'static, Vec<Action>>,
+    timeout: Duration,
+    message_name: String,
+) -> BoxFuture<'static, Vec<Action>> {
+    async move {
+        match select(fut, Delay::new(timeout)).await {
+            Either::Left((actions, _)) => actions,
+            Either::Right(_) => {
+                warn!(
+                    "message {} processing timed out after {:?}",
+                    message_name, timeout
+                );
+                vec![]
+            }
+        }
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ActionVarScope, MessageArgs, MessageHandlers, SupportVersion, TypedData, VarintString,
+    };
+    use futures::executor::block_on;
+
+    fn agent_state(config: AgentConfig, handlers: MessageHandlers) -> Arc<AgentState> {
+        Arc::new(AgentState::new(config, handlers))
+    }
+
+    fn handlers() -> MessageHandlers {
+        let mut handlers = MessageHandlers::new();
+        handlers.register(
+            "demo",
+            |_: Arc<ConnectionContext>, args: MessageArgs| async move {
+                vec![Action::set_val(
+                    ActionVarScope::TRANSACTION,
+                    VarintString::new("method"),
+                    args.get(&VarintString::new("arg_method")).unwrap().clone(),
+                )]
             },
-            _ => panic!("not support"),
+        );
+        handlers
+    }
+
+    // Runs the handlers like `Connection` does.
+    fn ack(frame: &Frame, notify_frame: NotifyFrame) -> (FrameHeader, FramePayload) {
+        let actions = block_on(frame.dispatch(notify_frame.payload.messages));
+        ack_frame(notify_frame.stream_id, notify_frame.frame_id, actions)
+    }
+
+    #[test]
+    fn test_handle() -> anyhow::Result<()> {
+        let mut frame = Frame::new(agent_state(Default::default(), handlers()));
+
+        let mut bytes = Bytes::from_static(b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x10pipelining,async\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57");
+        let (received, do_close) = frame.receive(&mut bytes)?;
+        assert!(!do_close);
+        let (frame_header, frame_payload) = match received {
+            Some(Received::Reply(frame_header, frame_payload)) => (frame_header, frame_payload),
+            _ => panic!("should reply now"),
         };
+        assert_eq!(frame_header.r#type, FrameType::AGENT_HELLO);
+        assert_eq!(
+            frame_payload.get_kv_list_value("capabilities"),
+            Some(&TypedData::STRING(VarintString::new("async,pipelining")))
+        );
+        assert_eq!(
+            frame.context().engine_id.as_deref(),
+            Some("6bdec4ec-6b9a-4705-83f4-8817766c0c57")
+        );
+        assert_eq!(frame.context().version, SupportVersion::parse("2.0"));
 
-        info!(
-            "write frame_header: {:?}, frame_payload: {:?}, do_close: {}",
-            frame_header_out, frame_payload_out, do_close
+        let mut bytes = Bytes::from_static(
+            b"\x03\0\0\0\x01\0\x01\x04demo\x02\narg_method\x08\x03GET\x08arg_path\x08\x01/",
         );
+        let (received, do_close) = frame.receive(&mut bytes)?;
+        assert!(!do_close);
+        let (frame_header, frame_payload) = match received {
+            Some(Received::Notify(notify_frame)) => ack(&frame, notify_frame),
+            _ => panic!("should reply later"),
+        };
+        assert_eq!(frame_header.r#type, FrameType::ACK);
+        assert_eq!(frame_header.stream_id.u64_val(), 0);
+        assert_eq!(frame_header.frame_id.u64_val(), 1);
+
+        let actions = frame_payload.get_list_of_actions().unwrap();
+        assert_eq!(actions.len(), 1);
+        match &actions[0] {
+            Action::SET_VAR { var_value, .. } => {
+                assert_eq!(var_value, &TypedData::STRING(VarintString::new("GET")))
+            }
+            _ => panic!("should SET_VAR"),
+        }
+
+        Ok(())
+    }
 
-        let mut buf: BytesMut = frame_header_out.into();
-        frame_payload_out.write_to(&mut buf);
+    #[test]
+    fn test_handle_fragmentation_not_negotiated() -> anyhow::Result<()> {
+        let mut frame = Frame::new(agent_state(Default::default(), handlers()));
+
+        let mut bytes = Bytes::from_static(b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x10pipelining,async\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57");
+        frame.receive(&mut bytes)?;
+        assert!(!frame
+            .context()
+            .has_capability(&HAProxyHelloFrameCapability::fragmentation));
+
+        let mut bytes =
+            Bytes::from_static(b"\x03\0\0\0\0\0\x01\x04demo\x02\narg_method\x08\x03GET");
+        let e = frame.receive(&mut bytes).err().unwrap();
+        assert_eq!(
+            FrameKnownError::from(&e),
+            FrameKnownError::payload_fragmentation_is_not_supported
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_unsupported_version() -> anyhow::Result<()> {
+        let mut frame = Frame::new(agent_state(Default::default(), handlers()));
+
+        let mut bytes = Bytes::from_static(b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x031.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\0\tengine-id\x08$6506a2ee-3942-4be8-a476-ff7550dbc6c3");
+        let e = frame.receive(&mut bytes).err().unwrap();
+        assert_eq!(
+            FrameKnownError::from(&e),
+            FrameKnownError::unsupported_version
+        );
+        assert_eq!(frame.context().version, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_fragmented_notify() -> anyhow::Result<()> {
+        let mut frame = Frame::new(agent_state(Default::default(), handlers()));
+
+        let mut bytes = Bytes::from_static(b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x1epipelining,async,fragmentation\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57");
+        frame.receive(&mut bytes)?;
+
+        let mut bytes = Bytes::from_static(b"\x03\0\0\0\0\0\x01\x04demo\x02\narg_meth");
+        assert!(frame.receive(&mut bytes)?.0.is_none());
+        let mut bytes = Bytes::from_static(b"\0\0\0\0\x01\0\x01od\x08\x03GET\x08arg_path\x08\x01/");
+        let (frame_header, frame_payload) = match frame.receive(&mut bytes)? {
+            (Some(Received::Notify(notify_frame)), false) => ack(&frame, notify_frame),
+            _ => panic!("should reply later"),
+        };
+        assert_eq!(frame_header.r#type, FrameType::ACK);
+        assert_eq!(frame_payload.get_list_of_actions().unwrap().len(), 1);
+
+        let mut bytes = Bytes::from_static(b"\x03\0\0\0\0\0\x02\x04demo\x02\narg_meth");
+        assert!(frame.receive(&mut bytes)?.0.is_none());
+        let mut bytes = Bytes::from_static(b"\0\0\0\0\x03\0\x02");
+        assert!(frame.receive(&mut bytes)?.0.is_none());
+
+        let mut bytes = Bytes::from_static(b"\0\0\0\0\x01\0\x03");
+        let e = frame.receive(&mut bytes).err().unwrap();
+        assert_eq!(
+            FrameKnownError::from(&e),
+            FrameKnownError::frame_id_not_found
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_state() -> anyhow::Result<()> {
+        let mut frame = Frame::new(agent_state(Default::default(), handlers()));
+        assert_eq!(frame.state(), ConnectionState::Connecting);
+
+        let mut bytes = Bytes::from_static(
+            b"\x03\0\0\0\x01\0\x01\x04demo\x02\narg_method\x08\x03GET\x08arg_path\x08\x01/",
+        );
+        match frame.receive(&mut bytes) {
+            Err(e @ FrameHandleError::InvalidState(..)) => assert_eq!(
+                FrameKnownError::from(&e),
+                FrameKnownError::invalid_frame_received
+            ),
+            _ => panic!("should err"),
+        }
+        assert_eq!(frame.state(), ConnectionState::Connecting);
+
+        let hello = b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x10pipelining,async\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57";
+        let (_, do_close) = frame.receive(&mut Bytes::from_static(hello))?;
+        assert!(!do_close);
+        assert_eq!(frame.state(), ConnectionState::Ready);
+
+        match frame.receive(&mut Bytes::from_static(hello)) {
+            Err(FrameHandleError::InvalidState(
+                FrameType::HAPROXY_HELLO,
+                ConnectionState::Ready,
+            )) => {}
+            _ => panic!("should err"),
+        }
+
+        let mut bytes =
+            Bytes::from_static(b"\x02\0\0\0\x01\0\0\x0bstatus-code\x03\0\x07message\x08\0");
+        let (_, do_close) = frame.receive(&mut bytes)?;
+        assert!(do_close);
+        assert_eq!(frame.state(), ConnectionState::Disconnecting);
+        frame.closed();
+        assert_eq!(frame.state(), ConnectionState::Closed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_healthcheck() -> anyhow::Result<()> {
+        let mut frame = Frame::new(agent_state(Default::default(), handlers()));
+
+        let mut bytes = Bytes::from_static(b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\0\x0bhealthcheck\x11\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57");
+        let (received, do_close) = frame.receive(&mut bytes)?;
+        assert!(do_close);
+        let frame_header = match received {
+            Some(Received::Reply(frame_header, _)) => frame_header,
+            _ => panic!("should reply now"),
+        };
+        assert_eq!(frame_header.r#type, FrameType::AGENT_HELLO);
+        assert_eq!(frame.state(), ConnectionState::Disconnecting);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_unexpected_frame() -> anyhow::Result<()> {
+        let mut frame = Frame::new(agent_state(Default::default(), handlers()));
+
+        // AGENT-HELLO and ACK are only ever sent by the agent.
+        for bytes in &[&b"\x65\0\0\0\x01\0\0"[..], &b"\x67\0\0\0\x01\x01\x01"[..]] {
+            match frame.receive(&mut Bytes::from_static(bytes)) {
+                Err(e @ FrameHandleError::InvalidState(..)) => assert_eq!(
+                    FrameKnownError::from(&e),
+                    FrameKnownError::invalid_frame_received
+                ),
+                _ => panic!("should err"),
+            }
+        }
+
+        match frame.receive(&mut Bytes::from_static(b"\x65\0\0")) {
+            Err(e @ FrameHandleError::ToFrameHeaderFailed(_)) => assert_eq!(
+                FrameKnownError::from(&e),
+                FrameKnownError::invalid_frame_received
+            ),
+            _ => panic!("should err"),
+        }
+
+        let (frame_header, frame_payload) =
+            frame.disconnect_frame(FrameKnownError::invalid_frame_received);
+        assert_eq!(frame_header.r#type, FrameType::AGENT_DISCONNECT);
+        assert_eq!(
+            frame_payload.get_kv_list_value("status-code"),
+            Some(&TypedData::UINT32(
+                FrameKnownError::invalid_frame_received.into()
+            ))
+        );
 
-        Ok((Some(buf), do_close))
+        Ok(())
     }
 }
@@ -1,27 +1,73 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use futures_codec::{Decoder, Encoder};
+use thiserror::Error;
 
 const U32_LENGTH: usize = std::mem::size_of::<u32>();
 
-pub struct FrameCodec();
+// https://github.com/haproxy/haproxy/blob/v2.1.0/doc/SPOE.txt#L769
+pub const MIN_FRAME_SIZE: u32 = 256;
+// tune.bufsize (16384) - 4
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16380;
 
-impl Encoder for FrameCodec {
-    type Item = Bytes;
-    type Error = anyhow::Error;
+#[derive(Clone, Debug)]
+pub struct FrameCodec {
+    max_frame_size: u32,
+}
 
-    fn encode(&mut self, src: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        dst.reserve(U32_LENGTH + src.len());
-        dst.put_u32(src.len() as u32);
-        dst.extend_from_slice(&src);
-        Ok(())
+impl FrameCodec {
+    pub fn new(max_frame_size: u32) -> Self {
+        Self { max_frame_size }
+    }
+
+    pub fn max_frame_size(&self) -> u32 {
+        self.max_frame_size
+    }
+
+    // Lowered once max-frame-size is negotiated.
+    pub fn set_max_frame_size(&mut self, max_frame_size: u32) {
+        self.max_frame_size = max_frame_size
     }
 }
 
-impl Decoder for FrameCodec {
-    type Item = Bytes;
-    type Error = anyhow::Error;
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+#[derive(Error, Debug)]
+pub enum FrameCodecError {
+    #[error("frame length {0} exceeds max-frame-size {1}")]
+    FrameTooBig(usize, u32),
+}
+
+// Length-prefixed frames, encoded into and decoded from the buffers of `Connection`.
+impl FrameCodec {
+    // `write` appends the frame to `dst` right after room for the length, which is filled
+    // in afterwards. Nothing is left in `dst` if the frame is too big.
+    pub fn encode_frame_with<F>(
+        &self,
+        dst: &mut BytesMut,
+        write: F,
+    ) -> Result<usize, FrameCodecError>
+    where
+        F: FnOnce(&mut BytesMut),
+    {
+        let start = dst.len();
+        dst.put_u32(0);
+        write(dst);
+
+        let len = dst.len() - start - U32_LENGTH;
+        let max_frame_size = self.max_frame_size();
+        if len > max_frame_size as usize {
+            dst.truncate(start);
+            return Err(FrameCodecError::FrameTooBig(len, max_frame_size));
+        }
+
+        dst[start..start + U32_LENGTH].copy_from_slice(&(len as u32).to_be_bytes());
+        Ok(len)
+    }
+
+    pub fn decode_frame(&self, src: &mut BytesMut) -> Result<Option<Bytes>, FrameCodecError> {
         if src.len() < U32_LENGTH {
             return Ok(None);
         }
@@ -30,12 +76,74 @@ impl Decoder for FrameCodec {
         len_bytes.copy_from_slice(&src[..U32_LENGTH]);
         let len = u32::from_be_bytes(len_bytes) as usize;
 
+        // Reject before buffering the rest of the frame.
+        let max_frame_size = self.max_frame_size();
+        if len > max_frame_size as usize {
+            return Err(FrameCodecError::FrameTooBig(len, max_frame_size));
+        }
+
         if src.len() - U32_LENGTH >= len {
             // Skip the length header we already read.
             src.advance(U32_LENGTH);
             Ok(Some(src.split_to(len).freeze()))
         } else {
+            src.reserve(U32_LENGTH + len - src.len());
             Ok(None)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() -> anyhow::Result<()> {
+        let mut codec = FrameCodec::new(MIN_FRAME_SIZE);
+
+        let mut src = BytesMut::from(&b"\0\0\0\x03ab"[..]);
+        assert_eq!(codec.decode_frame(&mut src)?, None);
+        src.extend_from_slice(b"c");
+        assert_eq!(
+            codec.decode_frame(&mut src)?,
+            Some(Bytes::from_static(b"abc"))
+        );
+        assert!(src.is_empty());
+
+        let mut src = BytesMut::from(&b"\0\0\x01\x01"[..]);
+        match codec.decode_frame(&mut src) {
+            Err(FrameCodecError::FrameTooBig(len, max)) => {
+                assert_eq!(len, 257);
+                assert_eq!(max, MIN_FRAME_SIZE);
+            }
+            _ => panic!("should err"),
+        }
+
+        codec.set_max_frame_size(1024);
+        assert_eq!(codec.max_frame_size(), 1024);
+        assert_eq!(codec.decode_frame(&mut src)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_frame_with() -> anyhow::Result<()> {
+        let codec = FrameCodec::new(MIN_FRAME_SIZE);
+
+        let mut dst = BytesMut::from(&b"x"[..]);
+        assert_eq!(
+            codec.encode_frame_with(&mut dst, |buf| buf.put_slice(b"abc"))?,
+            3
+        );
+        assert_eq!(&dst[..], b"x\0\0\0\x03abc");
+
+        let too_big = [0u8; MIN_FRAME_SIZE as usize + 1];
+        match codec.encode_frame_with(&mut dst, |buf| buf.put_slice(&too_big)) {
+            Err(FrameCodecError::FrameTooBig(len, _)) => assert_eq!(len, too_big.len()),
+            _ => panic!("should err"),
+        }
+        assert_eq!(&dst[..], b"x\0\0\0\x03abc");
+
+        Ok(())
+    }
+}
@@ -57,10 +57,8 @@ impl TryFrom<&mut Bytes> for FrameFlags {
         let r#u32 = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
         let flags = FrameFlags(r#u32);
 
-        if flags.is_abort() {
-            if flags.is_fin() == false {
-                return Err(FrameFlagsParseError::FINNotSet);
-            }
+        if flags.is_abort() && !flags.is_fin() {
+            return Err(FrameFlagsParseError::FINNotSet);
         }
 
         Ok(flags)
@@ -70,7 +68,6 @@ impl TryFrom<&mut Bytes> for FrameFlags {
 impl FrameFlags {
     pub fn write_to(&self, buf: &mut BytesMut) {
         buf.put_u32(self.0);
-        ()
     }
 }
 
@@ -101,28 +98,28 @@ mod tests {
         let mut bytes = Bytes::from_static(b"\0\0\0\x00");
         let bytes = &mut bytes;
         let frame_flags: FrameFlags = bytes.try_into()?;
-        assert_eq!(frame_flags.is_fin(), false);
-        assert_eq!(frame_flags.is_abort(), false);
+        assert!(!frame_flags.is_fin());
+        assert!(!frame_flags.is_abort());
 
         let mut bytes = Bytes::from_static(b"\0\0\0\x01");
         let bytes = &mut bytes;
         let frame_flags: FrameFlags = bytes.try_into()?;
-        assert_eq!(frame_flags.is_fin(), true);
-        assert_eq!(frame_flags.is_abort(), false);
+        assert!(frame_flags.is_fin());
+        assert!(!frame_flags.is_abort());
 
         let mut bytes = Bytes::from_static(b"\0\0\0\x02");
         let bytes = &mut bytes;
         if let Err(e) = FrameFlags::try_from(bytes) {
             assert_eq!(e, FrameFlagsParseError::FINNotSet);
         } else {
-            assert!(false, "should err");
+            panic!("should err");
         }
 
         let mut bytes = Bytes::from_static(b"\0\0\0\x03");
         let bytes = &mut bytes;
         let frame_flags: FrameFlags = bytes.try_into()?;
-        assert_eq!(frame_flags.is_fin(), true);
-        assert_eq!(frame_flags.is_abort(), true);
+        assert!(frame_flags.is_fin());
+        assert!(frame_flags.is_abort());
 
         Ok(())
     }
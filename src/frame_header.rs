@@ -52,12 +52,21 @@ impl TryFrom<&mut Bytes> for FrameHeader {
 impl From<FrameHeader> for BytesMut {
     fn from(header: FrameHeader) -> Self {
         let mut buf = BytesMut::new();
+        header.write_to(&mut buf);
+        buf
+    }
+}
 
-        header.r#type.write_to(&mut buf);
-        header.flags.write_to(&mut buf);
-        buf.extend_from_slice(BytesMut::from(header.stream_id).as_ref());
-        buf.extend_from_slice(BytesMut::from(header.frame_id).as_ref());
+impl FrameHeader {
+    pub fn write_to(&self, buf: &mut BytesMut) {
+        self.r#type.clone().write_to(buf);
+        self.flags.write_to(buf);
+        self.stream_id.write_to(buf);
+        self.frame_id.write_to(buf);
+    }
 
-        buf
+    // TYPE and FLAGS take 5 bytes, followed by STREAM-ID and FRAME-ID.
+    pub fn encoded_len(&self) -> usize {
+        5 + self.stream_id.encoded_len() + self.frame_id.encoded_len()
     }
 }
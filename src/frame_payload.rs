@@ -1,4 +1,4 @@
-use crate::{Action, FrameType, NBArgs, TypedData, VarintString};
+use crate::{Action, FrameType, MessageArgs, NBArgs, TypedData, VarintString};
 use bytes::{Bytes, BytesMut};
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
@@ -7,17 +7,23 @@ use thiserror::Error;
 #[derive(Clone, Debug)]
 #[allow(non_camel_case_types)]
 pub enum FramePayload {
-    LIST_OF_MESSAGES(HashMap<VarintString, HashMap<VarintString, TypedData>>),
+    // In the order of the frame, see `MessageArgs`.
+    LIST_OF_MESSAGES(Vec<(VarintString, MessageArgs)>),
     LIST_OF_ACTIONS(Vec<Action>),
     KV_LIST(HashMap<VarintString, TypedData>),
 }
 
 impl FramePayload {
-    pub fn get_list_of_messages(
-        &self,
-    ) -> Option<HashMap<VarintString, HashMap<VarintString, TypedData>>> {
+    pub fn get_list_of_messages(&self) -> Option<&[(VarintString, MessageArgs)]> {
         match self {
-            Self::LIST_OF_MESSAGES(hash) => Some(hash.to_owned()),
+            Self::LIST_OF_MESSAGES(messages) => Some(messages),
+            _ => None,
+        }
+    }
+
+    pub fn into_list_of_messages(self) -> Option<Vec<(VarintString, MessageArgs)>> {
+        match self {
+            Self::LIST_OF_MESSAGES(messages) => Some(messages),
             _ => None,
         }
     }
@@ -60,6 +66,8 @@ pub enum FramePayloadParseError {
     InvalidListOfMessagesKvListValue,
     #[error("invalid LIST_OF_ACTIONS")]
     InvalidListOfActions,
+    #[error("frame type {0:?} has no payload of its own")]
+    UnsupportedFrameType(FrameType),
 }
 
 impl TryFrom<(&mut Bytes, &FrameType)> for FramePayload {
@@ -75,14 +83,18 @@ impl TryFrom<(&mut Bytes, &FrameType)> for FramePayload {
             FrameType::AGENT_DISCONNECT => FramePayloadType::KV_LIST,
             FrameType::NOTIFY => FramePayloadType::LIST_OF_MESSAGES,
             FrameType::ACK => FramePayloadType::LIST_OF_ACTIONS,
-            FrameType::UNSET => panic!("not support"),
+            FrameType::UNSET => {
+                return Err(FramePayloadParseError::UnsupportedFrameType(
+                    frame_type.to_owned(),
+                ))
+            }
         };
 
         match r#type {
             FramePayloadType::LIST_OF_MESSAGES => {
-                let mut hash = HashMap::<VarintString, HashMap<VarintString, TypedData>>::new();
+                let mut messages = vec![];
 
-                while bytes.len() > 0 {
+                while !bytes.is_empty() {
                     let name: VarintString = bytes
                         .try_into()
                         .map_err(|_| FramePayloadParseError::InvalidListOfMessagesMessageName)?;
@@ -91,7 +103,7 @@ impl TryFrom<(&mut Bytes, &FrameType)> for FramePayload {
                         .try_into()
                         .map_err(|_| FramePayloadParseError::InvalidListOfMessagesNBArgs)?;
 
-                    let mut hash_kv = HashMap::<VarintString, TypedData>::new();
+                    let mut args = MessageArgs::with_capacity(nb_args.val() as usize);
                     for _ in 0..nb_args.val() {
                         let name: VarintString = bytes
                             .try_into()
@@ -99,18 +111,18 @@ impl TryFrom<(&mut Bytes, &FrameType)> for FramePayload {
                         let value: TypedData = bytes.try_into().map_err(|_| {
                             FramePayloadParseError::InvalidListOfMessagesKvListValue
                         })?;
-                        hash_kv.insert(name, value);
+                        args.push(name, value);
                     }
 
-                    hash.insert(name, hash_kv);
+                    messages.push((name, args));
                 }
 
-                Ok(Self::LIST_OF_MESSAGES(hash))
+                Ok(Self::LIST_OF_MESSAGES(messages))
             }
             FramePayloadType::LIST_OF_ACTIONS => {
                 let mut actions: Vec<Action> = vec![];
 
-                while bytes.len() > 0 {
+                while !bytes.is_empty() {
                     let action: Action = bytes
                         .try_into()
                         .map_err(|_| FramePayloadParseError::InvalidListOfActions)?;
@@ -121,7 +133,7 @@ impl TryFrom<(&mut Bytes, &FrameType)> for FramePayload {
             FramePayloadType::KV_LIST => {
                 let mut hash = HashMap::<VarintString, TypedData>::new();
 
-                while bytes.len() > 0 {
+                while !bytes.is_empty() {
                     let name: VarintString = bytes
                         .try_into()
                         .map_err(|_| FramePayloadParseError::InvalidKvListName)?;
@@ -146,12 +158,12 @@ impl FramePayload {
                     v.write_to(buf);
                 }
             }
-            FramePayload::LIST_OF_MESSAGES(hash) => {
-                for (k, hash_kv) in hash {
+            FramePayload::LIST_OF_MESSAGES(messages) => {
+                for (k, args) in messages {
                     k.write_to(buf);
-                    NBArgs::new(hash_kv.len() as u8).write_to(buf);
+                    NBArgs::new(args.len() as u8).write_to(buf);
 
-                    for (k, v) in hash_kv {
+                    for (k, v) in args {
                         k.write_to(buf);
                         v.write_to(buf);
                     }
@@ -163,7 +175,5 @@ impl FramePayload {
                 }
             }
         }
-
-        ()
     }
 }
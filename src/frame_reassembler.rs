@@ -0,0 +1,272 @@
+use crate::{FrameFlags, FrameHeader, FrameKnownError, FrameType, Varint};
+use bytes::{Bytes, BytesMut};
+use thiserror::Error;
+
+// https://github.com/haproxy/haproxy/blob/v2.1.0/doc/SPOE.txt#L749
+// Only one fragmented frame may be in progress at a time: it starts with a NOTIFY frame
+// without the FIN bit and goes on with UNSET frames carrying the same STREAM-ID and
+// FRAME-ID, until one of them has the FIN bit (or the ABORT bit) set.
+#[derive(Debug)]
+pub struct FrameReassembler {
+    pending: Option<PendingFrame>,
+    max_size: usize,
+}
+
+#[derive(Debug)]
+struct PendingFrame {
+    r#type: FrameType,
+    stream_id: Varint,
+    frame_id: Varint,
+    buf: BytesMut,
+}
+
+#[derive(Debug)]
+pub enum Reassembled {
+    Complete(FrameHeader, Bytes),
+    Incomplete,
+    Aborted(FrameHeader),
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub enum FrameReassemblyError {
+    #[error("only NOTIFY frames with a FRAME-ID can be fragmented")]
+    InvalidFragmentedFrame,
+    #[error("new frame received while a fragmented frame is in progress")]
+    InterlacedFrames,
+    #[error("continuation frame does not match any fragmented frame")]
+    FrameIdNotFound,
+    #[error("fragmented frame exceeds {0} bytes")]
+    TooBig(usize),
+}
+
+impl From<&FrameReassemblyError> for FrameKnownError {
+    fn from(e: &FrameReassemblyError) -> Self {
+        match e {
+            FrameReassemblyError::InvalidFragmentedFrame => Self::invalid_frame_received,
+            FrameReassemblyError::InterlacedFrames => Self::invalid_interlaced_frames,
+            FrameReassemblyError::FrameIdNotFound => Self::frame_id_not_found,
+            FrameReassemblyError::TooBig(_) => Self::resource_allocation_error,
+        }
+    }
+}
+
+impl FrameReassembler {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            pending: None,
+            max_size,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn buffered_size(&self) -> usize {
+        self.pending.as_ref().map(|x| x.buf.len()).unwrap_or(0)
+    }
+
+    pub fn push(
+        &mut self,
+        frame_header: FrameHeader,
+        payload: Bytes,
+    ) -> Result<Reassembled, FrameReassemblyError> {
+        if frame_header.r#type != FrameType::UNSET {
+            if self.pending.is_some() {
+                return Err(FrameReassemblyError::InterlacedFrames);
+            }
+
+            if frame_header.flags.is_abort() {
+                return Ok(Reassembled::Aborted(frame_header));
+            }
+
+            if frame_header.flags.is_fin() {
+                return Ok(Reassembled::Complete(frame_header, payload));
+            }
+
+            if frame_header.r#type != FrameType::NOTIFY || frame_header.frame_id.u64_val() == 0 {
+                return Err(FrameReassemblyError::InvalidFragmentedFrame);
+            }
+            if payload.len() > self.max_size {
+                return Err(FrameReassemblyError::TooBig(self.max_size));
+            }
+
+            self.pending = Some(PendingFrame {
+                r#type: frame_header.r#type,
+                stream_id: frame_header.stream_id,
+                frame_id: frame_header.frame_id,
+                buf: BytesMut::from(&payload[..]),
+            });
+
+            return Ok(Reassembled::Incomplete);
+        }
+
+        let pending = match &mut self.pending {
+            Some(pending) => pending,
+            None => return Err(FrameReassemblyError::FrameIdNotFound),
+        };
+        if pending.stream_id != frame_header.stream_id || pending.frame_id != frame_header.frame_id
+        {
+            return Err(FrameReassemblyError::InterlacedFrames);
+        }
+
+        if frame_header.flags.is_abort() {
+            let pending = self.pending.take().unwrap();
+            return Ok(Reassembled::Aborted(FrameHeader {
+                r#type: pending.r#type,
+                flags: frame_header.flags,
+                stream_id: pending.stream_id,
+                frame_id: pending.frame_id,
+            }));
+        }
+
+        if pending.buf.len() + payload.len() > self.max_size {
+            self.pending = None;
+            return Err(FrameReassemblyError::TooBig(self.max_size));
+        }
+        pending.buf.extend_from_slice(&payload[..]);
+
+        if !frame_header.flags.is_fin() {
+            return Ok(Reassembled::Incomplete);
+        }
+
+        let pending = self.pending.take().unwrap();
+        let frame_header = FrameHeader {
+            r#type: pending.r#type,
+            flags: FrameFlags::new(true, false),
+            stream_id: pending.stream_id,
+            frame_id: pending.frame_id,
+        };
+
+        Ok(Reassembled::Complete(frame_header, pending.buf.freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(r#type: FrameType, is_fin: bool, is_abort: bool, frame_id: u32) -> FrameHeader {
+        FrameHeader {
+            r#type,
+            flags: FrameFlags::new(is_fin, is_abort),
+            stream_id: Varint::from(1_u32),
+            frame_id: Varint::from(frame_id),
+        }
+    }
+
+    #[test]
+    fn test_push() -> anyhow::Result<()> {
+        let mut reassembler = FrameReassembler::new(16);
+
+        match reassembler.push(
+            header(FrameType::NOTIFY, true, false, 1),
+            Bytes::from_static(b"abc"),
+        )? {
+            Reassembled::Complete(_, payload) => assert_eq!(&payload[..], b"abc"),
+            _ => panic!("should complete"),
+        }
+
+        match reassembler.push(
+            header(FrameType::NOTIFY, false, false, 2),
+            Bytes::from_static(b"abc"),
+        )? {
+            Reassembled::Incomplete => {}
+            _ => panic!("should be incomplete"),
+        }
+        assert!(reassembler.is_pending());
+        match reassembler.push(
+            header(FrameType::UNSET, false, false, 2),
+            Bytes::from_static(b"def"),
+        )? {
+            Reassembled::Incomplete => {}
+            _ => panic!("should be incomplete"),
+        }
+        assert_eq!(reassembler.buffered_size(), 6);
+        match reassembler.push(
+            header(FrameType::UNSET, true, false, 2),
+            Bytes::from_static(b"g"),
+        )? {
+            Reassembled::Complete(frame_header, payload) => {
+                assert_eq!(frame_header.r#type, FrameType::NOTIFY);
+                assert_eq!(frame_header.frame_id.u64_val(), 2);
+                assert_eq!(&payload[..], b"abcdefg");
+            }
+            _ => panic!("should complete"),
+        }
+        assert!(!reassembler.is_pending());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_abort() -> anyhow::Result<()> {
+        let mut reassembler = FrameReassembler::new(16);
+
+        reassembler.push(
+            header(FrameType::NOTIFY, false, false, 1),
+            Bytes::from_static(b"abc"),
+        )?;
+        match reassembler.push(
+            header(FrameType::UNSET, true, true, 1),
+            Bytes::from_static(b""),
+        )? {
+            Reassembled::Aborted(frame_header) => assert_eq!(frame_header.frame_id.u64_val(), 1),
+            _ => panic!("should abort"),
+        }
+        assert!(!reassembler.is_pending());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_error() -> anyhow::Result<()> {
+        let mut reassembler = FrameReassembler::new(4);
+
+        assert_eq!(
+            reassembler
+                .push(header(FrameType::UNSET, true, false, 1), Bytes::new())
+                .unwrap_err(),
+            FrameReassemblyError::FrameIdNotFound
+        );
+
+        assert_eq!(
+            reassembler
+                .push(
+                    header(FrameType::HAPROXY_HELLO, false, false, 0),
+                    Bytes::new()
+                )
+                .unwrap_err(),
+            FrameReassemblyError::InvalidFragmentedFrame
+        );
+
+        reassembler.push(
+            header(FrameType::NOTIFY, false, false, 1),
+            Bytes::from_static(b"abc"),
+        )?;
+        assert_eq!(
+            reassembler
+                .push(header(FrameType::NOTIFY, true, false, 2), Bytes::new())
+                .unwrap_err(),
+            FrameReassemblyError::InterlacedFrames
+        );
+        assert_eq!(
+            reassembler
+                .push(header(FrameType::UNSET, true, false, 2), Bytes::new())
+                .unwrap_err(),
+            FrameReassemblyError::InterlacedFrames
+        );
+        assert_eq!(
+            reassembler
+                .push(
+                    header(FrameType::UNSET, false, false, 1),
+                    Bytes::from_static(b"de")
+                )
+                .unwrap_err(),
+            FrameReassemblyError::TooBig(4)
+        );
+        assert!(!reassembler.is_pending());
+
+        Ok(())
+    }
+}
@@ -29,7 +29,7 @@ impl TryFrom<&mut Bytes> for FrameType {
     type Error = FrameTypeParseError;
 
     fn try_from(bytes: &mut Bytes) -> Result<Self, FrameTypeParseError> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(FrameTypeParseError::InsufficientBytes);
         }
         let b = bytes.split_to(1);
@@ -42,7 +42,6 @@ impl TryFrom<&mut Bytes> for FrameType {
 impl FrameType {
     pub fn write_to(self, buf: &mut BytesMut) {
         buf.put_u8(self.into());
-        ()
     }
 }
 
@@ -73,7 +72,7 @@ mod tests {
         if let Err(e) = FrameType::try_from(bytes) {
             assert_eq!(e, FrameTypeParseError::Invalid);
         } else {
-            assert!(false, "should err");
+            panic!("should err");
         }
 
         Ok(())
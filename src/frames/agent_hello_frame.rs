@@ -32,8 +32,6 @@ impl AgentHelloFramePayload {
         max_frame_size: u32,
         capabilities: Vec<HAProxyHelloFrameCapability>,
     ) -> Self {
-        let max_frame_size = max_frame_size.max(256);
-
         Self {
             version,
             max_frame_size,
@@ -43,16 +41,15 @@ impl AgentHelloFramePayload {
 
     pub fn from_haproxy_hello_frame_payload(
         haproxy_hello_frame_payload: HAProxyHelloFramePayload,
-    ) -> Self {
-        Self::new(
+    ) -> Option<Self> {
+        Some(Self::new(
             haproxy_hello_frame_payload
                 .supported_versions
-                .first()
-                .unwrap()
+                .first()?
                 .to_owned(),
             haproxy_hello_frame_payload.max_frame_size,
             haproxy_hello_frame_payload.capabilities,
-        )
+        ))
     }
 }
 
@@ -99,8 +99,8 @@ mod tests {
         println!("{:?}", frame_payload);
 
         assert_eq!(frame_header.r#type, FrameType::HAPROXY_DISCONNECT);
-        assert_eq!(frame_header.flags.is_fin(), true);
-        assert_eq!(frame_header.flags.is_abort(), false);
+        assert!(frame_header.flags.is_fin());
+        assert!(!frame_header.flags.is_abort());
         assert_eq!(frame_header.stream_id.u64_val(), 0);
         assert_eq!(frame_header.frame_id.u64_val(), 0);
 
@@ -1,4 +1,4 @@
-use crate::{FrameHeader, FramePayload, SupportVersion};
+use crate::{FrameHeader, FrameKnownError, FramePayload, SupportVersion};
 use std::convert::TryFrom;
 use std::str::FromStr;
 use strum_macros::EnumString;
@@ -21,7 +21,7 @@ pub struct HAProxyHelloFramePayload {
 }
 
 // https://github.com/haproxy/haproxy/blob/v2.1.0/src/flt_spoe.c#L446
-#[derive(EnumString, Clone, Debug, Display)]
+#[derive(EnumString, PartialEq, Eq, Clone, Debug, Display)]
 #[allow(non_camel_case_types)]
 pub enum HAProxyHelloFrameCapability {
     #[strum(serialize = "pipelining")]
@@ -32,6 +32,8 @@ pub enum HAProxyHelloFrameCapability {
     fragmentation,
 }
 
+impl_deserialize_from_str!(HAProxyHelloFrameCapability);
+
 #[derive(Error, Debug)]
 #[allow(non_camel_case_types)]
 pub enum HAProxyHelloFrameParseError {
@@ -45,6 +47,29 @@ pub enum HAProxyHelloFrameParseError {
     FieldValueInvalid(String),
 }
 
+impl From<&HAProxyHelloFrameParseError> for FrameKnownError {
+    fn from(e: &HAProxyHelloFrameParseError) -> Self {
+        match e {
+            HAProxyHelloFrameParseError::FieldNotFound(name)
+                if name == &HAProxyHelloFramePayload::supported_versions_name() =>
+            {
+                Self::version_value_not_found
+            }
+            HAProxyHelloFrameParseError::FieldNotFound(name)
+                if name == &HAProxyHelloFramePayload::max_frame_size_name() =>
+            {
+                Self::max_frame_size_value_not_found
+            }
+            HAProxyHelloFrameParseError::FieldNotFound(name)
+                if name == &HAProxyHelloFramePayload::capabilities_name() =>
+            {
+                Self::capabilities_value_not_found
+            }
+            _ => Self::invalid_frame_received,
+        }
+    }
+}
+
 impl TryFrom<(FrameHeader, FramePayload)> for HAProxyHelloFrame {
     type Error = HAProxyHelloFrameParseError;
     fn try_from(t: (FrameHeader, FramePayload)) -> Result<Self, HAProxyHelloFrameParseError> {
@@ -69,7 +94,7 @@ impl TryFrom<(FrameHeader, FramePayload)> for HAProxyHelloFrame {
             ))?
             .val()
             .split(",")
-            .map(|x| SupportVersion::parse(x))
+            .map(SupportVersion::parse)
             .collect();
 
         let mut supported_versions: Vec<SupportVersion> = vec![];
@@ -145,9 +170,9 @@ impl TryFrom<(FrameHeader, FramePayload)> for HAProxyHelloFrame {
             .map(|x| x.val());
 
         let payload = HAProxyHelloFramePayload {
-            supported_versions: supported_versions,
+            supported_versions,
             max_frame_size: max_frame_size.to_owned(),
-            capabilities: capabilities,
+            capabilities,
             healthcheck: healthcheck.map(|x| x.to_owned()),
             engine_id: engine_id.map(|x| x.to_owned()),
         };
@@ -183,8 +208,8 @@ mod tests {
         println!("{:?}", frame_payload);
 
         assert_eq!(frame_header.r#type, FrameType::HAPROXY_HELLO);
-        assert_eq!(frame_header.flags.is_fin(), true);
-        assert_eq!(frame_header.flags.is_abort(), false);
+        assert!(frame_header.flags.is_fin());
+        assert!(!frame_header.flags.is_abort());
         assert_eq!(frame_header.stream_id.u64_val(), 0);
         assert_eq!(frame_header.frame_id.u64_val(), 0);
 
@@ -198,4 +223,22 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_from_field_not_found() -> anyhow::Result<()> {
+        let bytes = b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0ccapabilities\x08\0\tengine-id\x08\x01x";
+        let mut bytes = Bytes::from_static(bytes);
+        let bytes = &mut bytes;
+
+        let frame_header: FrameHeader = bytes.try_into()?;
+        let frame_payload: FramePayload = (bytes, &frame_header.r#type).try_into()?;
+
+        let e = HAProxyHelloFrame::try_from((frame_header, frame_payload)).unwrap_err();
+        assert_eq!(
+            FrameKnownError::from(&e),
+            FrameKnownError::max_frame_size_value_not_found
+        );
+
+        Ok(())
+    }
 }
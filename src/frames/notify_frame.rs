@@ -1,5 +1,4 @@
-use crate::{FrameFlags, FrameHeader, FramePayload, TypedData, Varint, VarintString};
-use std::collections::HashMap;
+use crate::{FrameFlags, FrameHeader, FramePayload, MessageArgs, Varint, VarintString};
 use std::convert::TryFrom;
 use thiserror::Error;
 
@@ -28,7 +27,17 @@ pub struct NotifyFrame {
 
 #[derive(Debug)]
 pub struct NotifyFramePayload {
-    pub messages: HashMap<VarintString, HashMap<VarintString, TypedData>>,
+    pub messages: Vec<(VarintString, MessageArgs)>,
+}
+
+impl NotifyFramePayload {
+    // The first message named `name`.
+    pub fn message(&self, name: &str) -> Option<&MessageArgs> {
+        self.messages
+            .iter()
+            .find(|(k, _)| k.val() == name)
+            .map(|(_, v)| v)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -52,16 +61,16 @@ impl TryFrom<(FrameHeader, FramePayload)> for NotifyFrame {
         }
 
         let messages = frame_payload
-            .get_list_of_messages()
+            .into_list_of_messages()
             .ok_or(NotifyFrameParseError::Invalid_Payload)?;
 
-        let payload = NotifyFramePayload { messages: messages };
+        let payload = NotifyFramePayload { messages };
 
         let frame = Self {
             flags: frame_header.flags,
             stream_id: frame_header.stream_id,
             frame_id: frame_header.frame_id,
-            payload: payload,
+            payload,
         };
 
         Ok(frame)
@@ -71,7 +80,7 @@ impl TryFrom<(FrameHeader, FramePayload)> for NotifyFrame {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::FrameType;
+    use crate::{FrameType, TypedData};
     use bytes::Bytes;
     use std::convert::TryInto;
 
@@ -92,8 +101,8 @@ mod tests {
         println!("{:?}", frame_payload);
 
         assert_eq!(frame_header.r#type, FrameType::NOTIFY);
-        assert_eq!(frame_header.flags.is_fin(), true);
-        assert_eq!(frame_header.flags.is_abort(), false);
+        assert!(frame_header.flags.is_fin());
+        assert!(!frame_header.flags.is_abort());
         assert_ne!(frame_header.frame_id.u64_val(), 0);
 
         let frame = NotifyFrame::try_from((frame_header, frame_payload))?;
@@ -101,11 +110,7 @@ mod tests {
 
         assert_eq!(frame.payload.messages.len(), 1);
 
-        let message = frame
-            .payload
-            .messages
-            .get(&VarintString::new("demo"))
-            .unwrap();
+        let message = frame.payload.message("demo").unwrap();
 
         assert_eq!(message.len(), 2);
         assert_eq!(
@@ -0,0 +1,96 @@
+//! HAProxy SPOE (Stream Processing Offload Engine) protocol implementation.
+//!
+//! https://github.com/haproxy/haproxy/blob/v2.1.0/doc/SPOE.txt
+
+extern crate strum;
+#[macro_use]
+extern crate strum_macros;
+
+#[macro_use]
+mod macros;
+
+mod varint;
+pub use varint::{Varint, VarintParseError};
+mod varint_binary;
+pub use varint_binary::{VarintBinary, VarintBinaryParseError};
+mod varint_string;
+pub use varint_string::{VarintString, VarintStringParseError};
+mod typed_data;
+pub use typed_data::{TypedData, TypedDataParseError};
+mod nb_args;
+pub use nb_args::{NBArgs, NBArgsParseError};
+mod action;
+pub use action::{Action, ActionParseError, ActionType, ActionVarScope};
+mod support_version;
+pub use support_version::{SupportVersion, SupportVersionParseError};
+
+mod frame_codec;
+pub use frame_codec::{FrameCodec, FrameCodecError, DEFAULT_MAX_FRAME_SIZE, MIN_FRAME_SIZE};
+mod frame_type;
+pub use frame_type::{FrameType, FrameTypeParseError};
+mod frame_flags;
+pub use frame_flags::{FrameFlags, FrameFlagsParseError};
+mod frame_header;
+pub use frame_header::{FrameHeader, FrameHeaderParseError};
+mod frame_payload;
+pub use frame_payload::{FramePayload, FramePayloadParseError, FramePayloadType};
+mod frame_reassembler;
+pub use frame_reassembler::{FrameReassembler, FrameReassemblyError, Reassembled};
+mod frame;
+pub use frame::{ack_frame, Frame, FrameHandleError, Received};
+mod frames;
+pub use frames::*;
+mod connection;
+pub use connection::{Connection, ConnectionError, ConnectionEvent};
+
+mod frame_error;
+pub use frame_error::FrameKnownError;
+
+mod listen_addr;
+pub use listen_addr::{ListenAddr, ListenAddrParseError, DEFAULT_UNIX_SOCKET_PATH};
+mod unix_socket_options;
+pub use unix_socket_options::{lookup_gid, lookup_uid, parse_mode, UnixSocketOptions};
+mod peer_credentials;
+pub use peer_credentials::{AllowedPeers, PeerCredentials};
+mod tls;
+pub use tls::{client_subject, TlsError, TlsServerConfig};
+mod listener;
+pub use listener::{
+    bind_listeners, format_listen_fds, parse_listen_fds, ListenFdsParseError, Listener,
+    ListenerSocket, LISTEN_FDS_ENV,
+};
+mod upgrade;
+pub use upgrade::{notify_upgrade_ready, upgrade, UPGRADE_READY_FD_ENV, UPGRADE_READY_TIMEOUT};
+mod supervisor;
+pub use supervisor::{worker_id, Supervisor, WORKER_ENV, WORKER_RESTART_DELAY};
+mod agent_config;
+pub use agent_config::{
+    AgentConfig, AgentConfigError, DEFAULT_MAX_FRAGMENTED_FRAME_SIZE, DEFAULT_SHUTDOWN_TIMEOUT,
+};
+mod shutdown;
+pub use shutdown::{ConnectionGuard, Shutdown};
+mod agent_state;
+pub use agent_state::AgentState;
+mod config_file;
+pub use config_file::{
+    ConfigFile, ConfigFileError, MessageConfig, Timeout, TimeoutConfig, TlsConfig, UnixSocketConfig,
+};
+mod connection_context;
+pub use connection_context::ConnectionContext;
+mod connection_state;
+pub use connection_state::ConnectionState;
+mod message_args;
+pub use message_args::MessageArgs;
+mod message_handler;
+pub use message_handler::{
+    HandlerOptions, MessageHandler, MessageHandlerFactories, MessageHandlerFactory, MessageHandlers,
+};
+mod builtin_handlers;
+
+mod agent;
+pub mod runtime;
+// smol is preferred when both runtime features are enabled.
+#[cfg(feature = "smol")]
+pub use runtime::smol::{accept_loop, connection_loop, run, serve};
+#[cfg(all(feature = "tokio", not(feature = "smol")))]
+pub use runtime::tokio::{accept_loop, connection_loop, run, serve};
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use thiserror::Error;
+
+pub const DEFAULT_UNIX_SOCKET_PATH: &str = "haproxy_run/spoa_demo.sock";
+
+// Same notation as the HAProxy `server` line, e.g. `server server-1 unix@/var/run/spoa_demo.sock`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    // Linux abstract namespace, no socket file is created.
+    Abstract(String),
+    // A listener opened by the parent process, either a fd number or a systemd
+    // `FileDescriptorName=` passed in LISTEN_FDNAMES.
+    Fd(String),
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub enum ListenAddrParseError {
+    #[error("invalid ipv4 address {0}")]
+    InvalidIpv4(String),
+    #[error("invalid ipv6 address {0}")]
+    InvalidIpv6(String),
+    #[error("empty unix socket path")]
+    EmptyUnixPath,
+    #[error("empty abstract socket name")]
+    EmptyAbstractName,
+    #[error("empty fd name")]
+    EmptyFdName,
+    #[error("invalid address {0}, expected ipv4@, ipv6@, unix@, abns@, fd@ or a socket path")]
+    Invalid(String),
+}
+
+impl FromStr for ListenAddr {
+    type Err = ListenAddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, ListenAddrParseError> {
+        let s = s.trim();
+
+        if let Some(addr) = s.strip_prefix("ipv4@") {
+            return match addr.parse::<SocketAddr>() {
+                Ok(addr) if addr.is_ipv4() => Ok(Self::Tcp(addr)),
+                _ => Err(ListenAddrParseError::InvalidIpv4(addr.to_owned())),
+            };
+        }
+        if let Some(addr) = s.strip_prefix("ipv6@") {
+            return match addr.parse::<SocketAddr>() {
+                Ok(addr) if addr.is_ipv6() => Ok(Self::Tcp(addr)),
+                _ => Err(ListenAddrParseError::InvalidIpv6(addr.to_owned())),
+            };
+        }
+        if let Some(path) = s.strip_prefix("unix@") {
+            if path.is_empty() {
+                return Err(ListenAddrParseError::EmptyUnixPath);
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        if let Some(name) = s.strip_prefix("abns@") {
+            if name.is_empty() {
+                return Err(ListenAddrParseError::EmptyAbstractName);
+            }
+            return Ok(Self::Abstract(name.to_owned()));
+        }
+        if let Some(name) = s.strip_prefix("fd@") {
+            if name.is_empty() {
+                return Err(ListenAddrParseError::EmptyFdName);
+            }
+            return Ok(Self::Fd(name.to_owned()));
+        }
+
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self::Tcp(addr));
+        }
+        if s.contains('/') {
+            return Ok(Self::Unix(PathBuf::from(s)));
+        }
+
+        Err(ListenAddrParseError::Invalid(s.to_owned()))
+    }
+}
+
+impl_deserialize_from_str!(ListenAddr);
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) if addr.is_ipv4() => write!(f, "ipv4@{}", addr),
+            Self::Tcp(addr) => write!(f, "ipv6@{}", addr),
+            Self::Unix(path) => write!(f, "unix@{}", path.display()),
+            Self::Abstract(name) => write!(f, "abns@{}", name),
+            Self::Fd(name) => write!(f, "fd@{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() -> anyhow::Result<()> {
+        let results: Vec<(&str, ListenAddr, &str)> = vec![
+            (
+                "ipv4@127.0.0.1:6001",
+                ListenAddr::Tcp("127.0.0.1:6001".parse()?),
+                "ipv4@127.0.0.1:6001",
+            ),
+            (
+                "0.0.0.0:6001",
+                ListenAddr::Tcp("0.0.0.0:6001".parse()?),
+                "ipv4@0.0.0.0:6001",
+            ),
+            (
+                "ipv6@[::1]:6001",
+                ListenAddr::Tcp("[::1]:6001".parse()?),
+                "ipv6@[::1]:6001",
+            ),
+            (
+                "unix@/var/run/spoa_demo.sock",
+                ListenAddr::Unix(PathBuf::from("/var/run/spoa_demo.sock")),
+                "unix@/var/run/spoa_demo.sock",
+            ),
+            (
+                "haproxy_run/spoa_demo.sock",
+                ListenAddr::Unix(PathBuf::from("haproxy_run/spoa_demo.sock")),
+                "unix@haproxy_run/spoa_demo.sock",
+            ),
+            (
+                "abns@spoa_demo",
+                ListenAddr::Abstract("spoa_demo".to_owned()),
+                "abns@spoa_demo",
+            ),
+            ("fd@spoa", ListenAddr::Fd("spoa".to_owned()), "fd@spoa"),
+        ];
+
+        for (s, listen_addr, display) in results {
+            assert_eq!(s.parse::<ListenAddr>()?, listen_addr);
+            assert_eq!(listen_addr.to_string(), display);
+        }
+
+        assert_eq!(
+            "ipv4@[::1]:6001".parse::<ListenAddr>(),
+            Err(ListenAddrParseError::InvalidIpv4("[::1]:6001".to_owned()))
+        );
+        assert_eq!(
+            "unix@".parse::<ListenAddr>(),
+            Err(ListenAddrParseError::EmptyUnixPath)
+        );
+        assert_eq!(
+            "abns@".parse::<ListenAddr>(),
+            Err(ListenAddrParseError::EmptyAbstractName)
+        );
+        assert_eq!(
+            "localhost".parse::<ListenAddr>(),
+            Err(ListenAddrParseError::Invalid("localhost".to_owned()))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{AgentConfig, ListenAddr, ListenAddrParseError, UnixSocketOptions};
+use log::*;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process;
+use thiserror::Error;
+
+// Set by the previous process on upgrade, e.g. `3=unix@haproxy_run/spoa_demo.sock;4=ipv4@127.0.0.1:6001`
+pub const LISTEN_FDS_ENV: &str = "SPOA_LISTEN_FDS";
+
+#[derive(Debug)]
+pub enum ListenerSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+// A listening socket, either bound by this process or inherited from the previous one.
+#[derive(Debug)]
+pub struct Listener {
+    addr: ListenAddr,
+    socket: ListenerSocket,
+}
+
+impl Listener {
+    pub fn bind(addr: &ListenAddr, config: &AgentConfig) -> io::Result<Self> {
+        let socket = match addr {
+            ListenAddr::Tcp(socket_addr) if config.reuse_port => {
+                ListenerSocket::Tcp(bind_reuse_port(socket_addr)?)
+            }
+            ListenAddr::Tcp(socket_addr) => ListenerSocket::Tcp(TcpListener::bind(socket_addr)?),
+            ListenAddr::Unix(path) => {
+                ListenerSocket::Unix(bind_unix(path, &config.unix_socket_options)?)
+            }
+            ListenAddr::Abstract(name) => ListenerSocket::Unix(bind_abstract(name)?),
+            ListenAddr::Fd(name) => {
+                let fd = match name.parse::<RawFd>() {
+                    Ok(fd) => fd,
+                    Err(_) => systemd_listen_fd(name).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("no fd named {} in LISTEN_FDNAMES", name),
+                        )
+                    })?,
+                };
+                return unsafe { Self::from_raw_fd(addr, fd) };
+            }
+        };
+
+        Ok(Self {
+            addr: addr.clone(),
+            socket,
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `fd` must be a listening socket owned by nothing else.
+    pub unsafe fn from_raw_fd(addr: &ListenAddr, fd: RawFd) -> io::Result<Self> {
+        let mut storage: libc::sockaddr_storage = mem::zeroed();
+        let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockname(fd, &mut storage as *mut _ as *mut libc::sockaddr, &mut len) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let socket = match storage.ss_family as libc::c_int {
+            libc::AF_INET | libc::AF_INET6 => ListenerSocket::Tcp(TcpListener::from_raw_fd(fd)),
+            libc::AF_UNIX => ListenerSocket::Unix(UnixListener::from_raw_fd(fd)),
+            family => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("fd {} of {} has unsupported family {}", fd, addr, family),
+                ))
+            }
+        };
+
+        Ok(Self {
+            addr: addr.clone(),
+            socket,
+        })
+    }
+
+    pub fn addr(&self) -> &ListenAddr {
+        &self.addr
+    }
+
+    pub fn into_socket(self) -> ListenerSocket {
+        self.socket
+    }
+}
+
+// Same options and backlog as `TcpListener::bind`, plus SO_REUSEPORT.
+fn bind_reuse_port(socket_addr: &SocketAddr) -> io::Result<TcpListener> {
+    let domain = match socket_addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&(*socket_addr).into())?;
+    socket.listen(128)?;
+
+    Ok(socket.into_tcp_listener())
+}
+
+// The socket file is created with the umask permissions, but connections are refused until
+// `listen`, which is only called once the mode and owner are applied. The file is removed
+// when either fails.
+fn bind_unix(path: &Path, options: &UnixSocketOptions) -> io::Result<UnixListener> {
+    let socket = Socket::new(Domain::unix(), Type::stream(), None)?;
+    socket.bind(&SockAddr::unix(path)?)?;
+    if let Err(e) = options.apply(path).and_then(|_| socket.listen(128)) {
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+
+    Ok(socket.into_unix_listener())
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> io::Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    UnixListener::bind_addr(&SocketAddr::from_abstract_name(name)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_name: &str) -> io::Result<UnixListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "abstract unix sockets are only supported on linux",
+    ))
+}
+
+// Listeners passed by systemd socket activation from fd 3 on, see sd_listen_fds(3).
+fn systemd_listen_fd(name: &str) -> Option<RawFd> {
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    let pid = env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    if pid != process::id() {
+        return None;
+    }
+    let nb_fds = env::var("LISTEN_FDS").ok()?.parse::<RawFd>().ok()?;
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+
+    names
+        .split(':')
+        .take(nb_fds as usize)
+        .position(|x| x == name)
+        .map(|i| SD_LISTEN_FDS_START + i as RawFd)
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match &self.socket {
+            ListenerSocket::Tcp(listener) => listener.as_raw_fd(),
+            ListenerSocket::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub enum ListenFdsParseError {
+    #[error("invalid entry {0}, expected fd=addr")]
+    InvalidEntry(String),
+    #[error("invalid fd {0}")]
+    InvalidFd(String),
+    #[error("invalid addr")]
+    InvalidAddr(#[from] ListenAddrParseError),
+}
+
+pub fn format_listen_fds(listen_fds: &[(RawFd, ListenAddr)]) -> String {
+    listen_fds
+        .iter()
+        .map(|(fd, addr)| format!("{}={}", fd, addr))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+pub fn parse_listen_fds(s: &str) -> Result<Vec<(RawFd, ListenAddr)>, ListenFdsParseError> {
+    s.split(';')
+        .filter(|x| !x.is_empty())
+        .map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            let (fd, addr) = match (parts.next(), parts.next()) {
+                (Some(fd), Some(addr)) => (fd, addr),
+                _ => return Err(ListenFdsParseError::InvalidEntry(entry.to_owned())),
+            };
+            let fd = fd
+                .parse::<RawFd>()
+                .map_err(|_| ListenFdsParseError::InvalidFd(fd.to_owned()))?;
+
+            Ok((fd, addr.parse()?))
+        })
+        .collect()
+}
+
+// Binds every address, reusing the sockets handed over by the previous process on upgrade
+// so that HAProxy never sees a refused connection.
+pub fn bind_listeners(config: &AgentConfig) -> anyhow::Result<Vec<Listener>> {
+    let mut inherited = match env::var(LISTEN_FDS_ENV) {
+        Ok(s) => parse_listen_fds(&s)?,
+        Err(_) => vec![],
+    };
+
+    let mut listeners = vec![];
+    for addr in &config.listen_addrs {
+        let listener = match inherited.iter().position(|(_, x)| x == addr) {
+            Some(i) => {
+                let (fd, addr) = inherited.remove(i);
+                info!("Inherited {} from fd {}", addr, fd);
+                unsafe { Listener::from_raw_fd(&addr, fd)? }
+            }
+            None => Listener::bind(addr, config)?,
+        };
+        listeners.push(listener);
+    }
+
+    for (fd, addr) in inherited {
+        warn!("{} is no longer configured, closing fd {}", addr, fd);
+        unsafe { libc::close(fd) };
+    }
+
+    Ok(listeners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_listen_fds() -> anyhow::Result<()> {
+        let listen_fds: Vec<(RawFd, ListenAddr)> = vec![
+            (3, "unix@haproxy_run/spoa_demo.sock".parse()?),
+            (4, "ipv6@[::1]:6001".parse()?),
+        ];
+
+        let s = format_listen_fds(&listen_fds);
+        assert_eq!(s, "3=unix@haproxy_run/spoa_demo.sock;4=ipv6@[::1]:6001");
+        assert_eq!(parse_listen_fds(&s)?, listen_fds);
+
+        assert_eq!(parse_listen_fds("")?, vec![]);
+        assert_eq!(
+            parse_listen_fds("3"),
+            Err(ListenFdsParseError::InvalidEntry("3".to_owned()))
+        );
+        assert_eq!(
+            parse_listen_fds("x=ipv4@127.0.0.1:6001"),
+            Err(ListenFdsParseError::InvalidFd("x".to_owned()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bind_unix() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("spoa.sock");
+        let options = UnixSocketOptions {
+            mode: Some(0o600),
+            ..Default::default()
+        };
+
+        let _listener = bind_unix(&path, &options)?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o7777, 0o600);
+        UnixStream::connect(&path)?;
+
+        // Already bound, the file of the first listener is left alone.
+        assert!(bind_unix(&path, &options).is_err());
+        assert!(path.exists());
+
+        let path = dir.path().join("no-such-dir").join("spoa.sock");
+        assert!(bind_unix(&path, &options).is_err());
+
+        Ok(())
+    }
+}
@@ -27,3 +27,18 @@ macro_rules! make_frame_kv_list_payload {
         }
     }
 }
+
+// Deserializes from the same notation as `FromStr`, e.g. `"ipv4@127.0.0.1:6001"`
+macro_rules! impl_deserialize_from_str {
+    ($type:ty) => {
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
@@ -1,150 +1,256 @@
-use futures::{SinkExt, TryStreamExt};
-use smol::{Async, Task, Timer};
-// use std::net::TcpListener;
-use std::os::unix::net::UnixListener;
-use std::path::PathBuf;
-use std::time::Duration;
-
-use futures_codec::Framed;
+use haproxy_spoa_example::{
+    bind_listeners, notify_upgrade_ready, run, serve, upgrade, worker_id, Action, ActionVarScope,
+    AgentConfig, AgentState, ConnectionContext, ListenAddr, Listener, MessageArgs,
+    MessageHandlerFactories, MessageHandlers, Supervisor, TypedData, VarintString,
+};
 use log::*;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR2};
+use signal_hook::iterator::Signals;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use structopt::StructOpt;
 
-extern crate strum;
-#[macro_use]
-extern crate strum_macros;
-
-#[macro_use]
-mod macros;
-
-mod varint;
-pub use varint::{Varint, VarintParseError};
-mod varint_binary;
-pub use varint_binary::{VarintBinary, VarintBinaryParseError};
-mod varint_string;
-pub use varint_string::{VarintString, VarintStringParseError};
-mod typed_data;
-pub use typed_data::{TypedData, TypedDataParseError};
-mod nb_args;
-pub use nb_args::{NBArgs, NBArgsParseError};
-mod action;
-pub use action::{Action, ActionParseError, ActionType, ActionVarScope};
-mod support_version;
-pub use support_version::SupportVersion;
-
-mod frame_codec;
-pub use frame_codec::FrameCodec;
-mod frame_type;
-pub use frame_type::{FrameType, FrameTypeParseError};
-mod frame_flags;
-pub use frame_flags::{FrameFlags, FrameFlagsParseError};
-mod frame_header;
-pub use frame_header::{FrameHeader, FrameHeaderParseError};
-mod frame_payload;
-pub use frame_payload::{FramePayload, FramePayloadParseError, FramePayloadType};
-mod frame;
-pub use frame::Frame;
-mod frames;
-pub use frames::*;
-
-mod frame_error;
-pub use frame_error::FrameKnownError;
+mod cli;
 
 fn main() -> anyhow::Result<()> {
-    env_logger::init();
-
-    ctrlc::set_handler(|| {
-        let sock_path = PathBuf::new()
-            .join("haproxy_run/spoa_demo.sock")
-            .to_str()
-            .unwrap()
-            .to_string();
-
-        match std::fs::remove_file(&sock_path) {
-            Ok(_) => info!("delete sock done"),
-            Err(e) => error!("delete sock error: {}", e),
+    let cli = cli::Cli::from_args();
+    cli.init_logger();
+
+    let (config, handlers) = load(&cli)?;
+
+    if cli.is_check() {
+        println!("configuration is valid");
+        for listen_addr in &config.listen_addrs {
+            println!("listen {}", listen_addr);
+        }
+        let mut message_names = handlers.message_names();
+        message_names.sort_unstable();
+        for message_name in message_names {
+            println!("message {}", message_name);
+        }
+        return Ok(());
+    }
+
+    match worker_id() {
+        Some(id) => {
+            info!("worker {}: serving with {} threads", id, config.threads);
+            // The supervisor owns the unix socket files.
+            KEEP_UNIX_SOCKETS.store(true, Ordering::SeqCst);
         }
+        None if config.workers > 1 => return supervise(config),
+        None => {}
+    }
 
-        std::process::exit(0)
-    })
-    .expect("Error setting Ctrl-C handler");
+    let listeners = bind_listeners(&config)?;
+    let listen_fds = listen_fds(&listeners);
 
-    // smol::run(accept_loop("127.0.0.1:6001"))
-    smol::run(async move {
-        let sock_path = PathBuf::new()
-            .join("haproxy_run/spoa_demo.sock")
-            .to_str()
-            .unwrap()
-            .to_string();
+    let threads = config.threads;
+    let agent_state = Arc::new(AgentState::new(config, handlers));
+    handle_signals(cli, agent_state.clone(), listen_fds)?;
+    notify_upgrade_ready()?;
 
-        let r = accept_loop(sock_path.as_str()).await;
+    run(threads, async move {
+        let r = serve(agent_state.clone(), listeners).await;
 
-        match r {
-            Ok(_) => info!("accept_loop done"),
-            Err(e) => error!("accept_loop error: {}", e),
+        match &r {
+            Ok(_) => info!("serve done"),
+            Err(e) => error!("serve error: {}", e),
         }
 
-        std::fs::remove_file(sock_path)?;
+        if !KEEP_UNIX_SOCKETS.load(Ordering::SeqCst) {
+            remove_unix_sockets(&agent_state.config().listen_addrs);
+        }
 
-        Ok(())
-    })
+        r
+    })?
 }
 
-async fn accept_loop(addr: &str) -> anyhow::Result<()> {
-    // let listener = Async::<TcpListener>::bind(addr)?;
-    let listener = Async::<UnixListener>::bind(addr)?;
+// Binds the listeners shared by the workers, then keeps the workers running until
+// SIGTERM/SIGINT.
+fn supervise(config: AgentConfig) -> anyhow::Result<()> {
+    // With reuse-port, every worker binds its own TCP listeners.
+    let mut shared_config = config.clone();
+    if config.reuse_port {
+        shared_config
+            .listen_addrs
+            .retain(|x| !matches!(x, ListenAddr::Tcp(_)));
+    }
+    let listeners = bind_listeners(&shared_config)?;
+    let listen_fds = listen_fds(&listeners);
+
+    let supervisor = Arc::new(Supervisor::new(config.workers, listen_fds.clone()));
+    if let Err(e) = supervisor.start() {
+        supervisor.stop(SIGTERM);
+        return Err(e);
+    }
+    supervise_signals(supervisor.clone(), listen_fds)?;
+    notify_upgrade_ready()?;
 
-    loop {
-        let (stream, peer_addr) = listener.accept().await?;
-        info!("Accepted client: {:?}", peer_addr);
+    supervisor.wait()?;
+    info!("workers stopped");
 
-        // Spawn a task that echoes messages from the client back to it.
-        Task::spawn(async move {
-            if let Err(e) = connection_loop(stream).await {
-                error!("connection error: {:?}", e)
-            } else {
-                info!("connection closed")
-            }
-        })
-        .detach();
+    if !KEEP_UNIX_SOCKETS.load(Ordering::SeqCst) {
+        remove_unix_sockets(&config.listen_addrs);
     }
+    drop(listeners);
+
+    Ok(())
 }
 
-async fn connection_loop<S>(stream: Async<S>) -> anyhow::Result<()>
-where
-    S: std::io::Read + std::io::Write,
-{
-    let mut framed = Framed::new(stream, FrameCodec());
+fn listen_fds(listeners: &[Listener]) -> Vec<(RawFd, ListenAddr)> {
+    listeners
+        .iter()
+        .map(|listener| (listener.as_raw_fd(), listener.addr().clone()))
+        .collect()
+}
 
-    let mut frame = Frame::new();
+fn load(cli: &cli::Cli) -> anyhow::Result<(AgentConfig, MessageHandlers)> {
+    let (config, mut handlers) = cli.load(&MessageHandlerFactories::builtin())?;
+    // Without any `[[messages]]` in the config file, serve the haproxy_conf demo.
+    if handlers.message_names().is_empty() {
+        handlers.register("msg-1", |_: Arc<ConnectionContext>, _: MessageArgs| async {
+            vec![Action::set_val(
+                ActionVarScope::TRANSACTION,
+                VarintString::new("var_name_1"),
+                TypedData::STRING(VarintString::new("var-value-1")),
+            )]
+        });
+    }
 
-    while let Some(mut bytes) = framed.try_next().await? {
-        debug!("read len: {} bytes: {:?}", bytes.len(), bytes);
-        let bytes = &mut bytes;
+    Ok((config, handlers))
+}
 
-        let (bytes, do_close) = frame.handle(bytes)?;
+// Set when another process keeps using the unix socket files: the new process the
+// listening sockets are handed over to on upgrade, or the supervisor of this worker.
+static KEEP_UNIX_SOCKETS: AtomicBool = AtomicBool::new(false);
 
-        if let Some(bytes) = bytes {
-            info!("write len: {}, bytes: {:?}", bytes.len(), bytes);
+// SIGHUP reloads the configuration, connections keep running and the current
+// configuration is kept if the new one is invalid.
+//
+// SIGUSR2 starts a new process with the same arguments and hands it the listening sockets,
+// then drains the current connections once it is ready.
+//
+// SIGTERM/SIGINT stop accepting connections and drain the current ones, a second
+// one exits right away.
+fn handle_signals(
+    cli: cli::Cli,
+    agent_state: Arc<AgentState>,
+    listen_fds: Vec<(RawFd, ListenAddr)>,
+) -> anyhow::Result<()> {
+    let mut signals = Signals::new([SIGHUP, SIGUSR2, SIGINT, SIGTERM])?;
 
-            Timer::after(Duration::from_nanos(100)).await;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => {
+                    info!("SIGHUP received, reloading configuration");
+                    match load(&cli) {
+                        Ok((config, handlers)) => {
+                            agent_state.reload(config, handlers);
+                            info!("configuration reloaded");
+                        }
+                        Err(e) => {
+                            error!("reload failed, keeping the current configuration: {:#}", e)
+                        }
+                    }
+                }
+                SIGUSR2 if worker_id().is_some() => {
+                    warn!("SIGUSR2 received by a worker, send it to the supervisor to upgrade");
+                }
+                SIGUSR2 if agent_state.shutdown().is_triggered() => {
+                    warn!("SIGUSR2 received while shutting down, upgrade skipped");
+                }
+                SIGUSR2 => {
+                    info!("SIGUSR2 received, upgrading");
+                    match upgrade(&listen_fds) {
+                        Ok(child) => {
+                            KEEP_UNIX_SOCKETS.store(true, Ordering::SeqCst);
+                            agent_state.shutdown().trigger();
+                            info!("upgrade: pid {} is ready, draining connections", child.id());
+                        }
+                        Err(e) => error!("upgrade failed, keeping on serving: {:#}", e),
+                    }
+                }
+                _ if agent_state.shutdown().trigger() => {
+                    info!("shutting down, draining connections");
+                }
+                _ => {
+                    warn!("shutting down immediately");
+                    if !KEEP_UNIX_SOCKETS.load(Ordering::SeqCst) {
+                        remove_unix_sockets(&agent_state.config().listen_addrs);
+                    }
 
-            framed.send(bytes.freeze()).await.map_err(|e| {
-                error!("on send {:?}", e);
-                e
-            })?;
+                    process::exit(1)
+                }
+            }
         }
+    });
+
+    Ok(())
+}
+
+// SIGHUP is forwarded to the workers, which reload their configuration.
+//
+// SIGUSR2 upgrades like a single process, the new supervisor starts its own workers.
+//
+// SIGTERM/SIGINT are forwarded to the workers, which drain their connections.
+fn supervise_signals(
+    supervisor: Arc<Supervisor>,
+    listen_fds: Vec<(RawFd, ListenAddr)>,
+) -> anyhow::Result<()> {
+    let mut signals = Signals::new([SIGHUP, SIGUSR2, SIGINT, SIGTERM])?;
+    // The unix listeners are always bound by the supervisor.
+    let listen_addrs: Vec<ListenAddr> = listen_fds.iter().map(|(_, x)| x.clone()).collect();
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => {
+                    info!("SIGHUP received, reloading workers");
+                    supervisor.signal(SIGHUP);
+                }
+                SIGUSR2 if supervisor.is_stopping() => {
+                    warn!("SIGUSR2 received while shutting down, upgrade skipped");
+                }
+                SIGUSR2 => {
+                    info!("SIGUSR2 received, upgrading");
+                    match upgrade(&listen_fds) {
+                        Ok(child) => {
+                            KEEP_UNIX_SOCKETS.store(true, Ordering::SeqCst);
+                            supervisor.stop(SIGTERM);
+                            info!("upgrade: pid {} is ready, draining workers", child.id());
+                        }
+                        Err(e) => error!("upgrade failed, keeping on serving: {:#}", e),
+                    }
+                }
+                _ if supervisor.stop(signal) => {
+                    info!("shutting down, draining workers");
+                }
+                _ => {
+                    warn!("shutting down immediately");
+                    if !KEEP_UNIX_SOCKETS.load(Ordering::SeqCst) {
+                        remove_unix_sockets(&listen_addrs);
+                    }
 
-        if do_close {
-            info!("do close");
-            framed.flush().await.map_err(|e| {
-                error!("on flush {:?}", e);
-                e
-            })?;
-            framed.close().await.map_err(|e| {
-                error!("on close {:?}", e);
-                e
-            })?;
+                    process::exit(1)
+                }
+            }
         }
-    }
+    });
 
     Ok(())
 }
+
+fn remove_unix_sockets(listen_addrs: &[ListenAddr]) {
+    for listen_addr in listen_addrs {
+        if let ListenAddr::Unix(path) = listen_addr {
+            match std::fs::remove_file(path) {
+                Ok(_) => info!("delete sock {} done", path.display()),
+                Err(e) => error!("delete sock {} error: {}", path.display(), e),
+            }
+        }
+    }
+}
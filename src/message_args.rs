@@ -0,0 +1,106 @@
+use crate::{TypedData, VarintString};
+use std::iter::FromIterator;
+use std::slice;
+use std::vec;
+
+// The arguments of a message in the order HAProxy sent them. Messages have a handful of
+// arguments, so they are looked up by scanning rather than hashing.
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct MessageArgs(Vec<(VarintString, TypedData)>);
+
+impl MessageArgs {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    // The first argument named `name`.
+    pub fn get<K: AsRef<str> + ?Sized>(&self, name: &K) -> Option<&TypedData> {
+        let name = name.as_ref();
+        self.0.iter().find(|(k, _)| k.val() == name).map(|(_, v)| v)
+    }
+
+    // Replaces the value of an argument of the same name, if any.
+    pub fn insert(&mut self, name: VarintString, value: TypedData) -> Option<TypedData> {
+        match self.0.iter_mut().find(|(k, _)| *k == name) {
+            Some((_, v)) => Some(std::mem::replace(v, value)),
+            None => {
+                self.0.push((name, value));
+                None
+            }
+        }
+    }
+
+    // Keeps arguments of the same name, as read from a NOTIFY frame.
+    pub fn push(&mut self, name: VarintString, value: TypedData) {
+        self.0.push((name, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (VarintString, TypedData)> {
+        self.0.iter()
+    }
+}
+
+impl IntoIterator for MessageArgs {
+    type Item = (VarintString, TypedData);
+    type IntoIter = vec::IntoIter<(VarintString, TypedData)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MessageArgs {
+    type Item = &'a (VarintString, TypedData);
+    type IntoIter = slice::Iter<'a, (VarintString, TypedData)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<(VarintString, TypedData)> for MessageArgs {
+    fn from_iter<I: IntoIterator<Item = (VarintString, TypedData)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() -> anyhow::Result<()> {
+        let mut args = MessageArgs::new();
+        args.push(VarintString::new("arg_path"), TypedData::NULL);
+        args.push(VarintString::new("arg_method"), TypedData::UINT32(1));
+        args.push(VarintString::new("arg_method"), TypedData::UINT32(2));
+
+        assert_eq!(args.get("arg_method"), Some(&TypedData::UINT32(1)));
+        assert_eq!(
+            args.get(&VarintString::new("arg_path")),
+            Some(&TypedData::NULL)
+        );
+        assert_eq!(args.get("arg_query"), None);
+
+        assert_eq!(
+            args.insert(VarintString::new("arg_path"), TypedData::BOOL(true)),
+            Some(TypedData::NULL)
+        );
+        let names = args.iter().map(|(k, _)| k.val()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["arg_path", "arg_method", "arg_method"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{Action, ConnectionContext, MessageArgs};
+use futures::future::{BoxFuture, FutureExt};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait MessageHandler: Send + Sync {
+    fn handle(
+        &self,
+        ctx: Arc<ConnectionContext>,
+        args: MessageArgs,
+    ) -> BoxFuture<'static, Vec<Action>>;
+}
+
+impl<F, Fut> MessageHandler for F
+where
+    F: Fn(Arc<ConnectionContext>, MessageArgs) -> Fut + Send + Sync,
+    Fut: Future<Output = Vec<Action>> + Send + 'static,
+{
+    fn handle(
+        &self,
+        ctx: Arc<ConnectionContext>,
+        args: MessageArgs,
+    ) -> BoxFuture<'static, Vec<Action>> {
+        self(ctx, args).boxed()
+    }
+}
+
+// Keyed by SPOE message name, e.g. `spoe-message msg-1`
+#[derive(Clone, Default)]
+pub struct MessageHandlers {
+    handlers: HashMap<String, Arc<dyn MessageHandler>>,
+}
+
+impl MessageHandlers {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn register<H>(&mut self, message_name: &str, handler: H) -> &mut Self
+    where
+        H: MessageHandler + 'static,
+    {
+        self.insert(message_name, Arc::new(handler))
+    }
+
+    pub fn insert(&mut self, message_name: &str, handler: Arc<dyn MessageHandler>) -> &mut Self {
+        self.handlers.insert(message_name.to_owned(), handler);
+        self
+    }
+
+    pub fn get(&self, message_name: &str) -> Option<&Arc<dyn MessageHandler>> {
+        self.handlers.get(message_name)
+    }
+
+    pub fn message_names(&self) -> Vec<&str> {
+        self.handlers.keys().map(|x| x.as_str()).collect()
+    }
+}
+
+// The `options` table of a `[[messages]]` entry in the config file.
+pub type HandlerOptions = toml::value::Table;
+
+pub trait MessageHandlerFactory: Send + Sync {
+    fn build(&self, options: &HandlerOptions) -> anyhow::Result<Arc<dyn MessageHandler>>;
+}
+
+impl<F> MessageHandlerFactory for F
+where
+    F: Fn(&HandlerOptions) -> anyhow::Result<Arc<dyn MessageHandler>> + Send + Sync,
+{
+    fn build(&self, options: &HandlerOptions) -> anyhow::Result<Arc<dyn MessageHandler>> {
+        self(options)
+    }
+}
+
+// Keyed by handler name, e.g. `handler = "set-var"` in the config file
+#[derive(Clone, Default)]
+pub struct MessageHandlerFactories {
+    factories: HashMap<String, Arc<dyn MessageHandlerFactory>>,
+}
+
+impl MessageHandlerFactories {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn register<F>(&mut self, handler_name: &str, factory: F) -> &mut Self
+    where
+        F: MessageHandlerFactory + 'static,
+    {
+        self.factories
+            .insert(handler_name.to_owned(), Arc::new(factory));
+        self
+    }
+
+    pub fn get(&self, handler_name: &str) -> Option<&Arc<dyn MessageHandlerFactory>> {
+        self.factories.get(handler_name)
+    }
+
+    pub fn handler_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.factories.keys().map(|x| x.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl fmt::Debug for MessageHandlerFactories {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageHandlerFactories")
+            .field("handler_names", &self.handler_names())
+            .finish()
+    }
+}
+
+impl fmt::Debug for MessageHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageHandlers")
+            .field("message_names", &self.message_names())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ActionVarScope, TypedData, VarintString};
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_register() -> anyhow::Result<()> {
+        let mut handlers = MessageHandlers::new();
+        handlers.register(
+            "msg-1",
+            |_: Arc<ConnectionContext>, args: MessageArgs| async move {
+                vec![Action::set_val(
+                    ActionVarScope::TRANSACTION,
+                    VarintString::new("nb_args"),
+                    TypedData::UINT32(args.len() as u32),
+                )]
+            },
+        );
+
+        assert!(handlers.get("msg-2").is_none());
+
+        let handler = handlers.get("msg-1").unwrap();
+        let mut args = MessageArgs::new();
+        args.insert(VarintString::new("arg_path"), TypedData::NULL);
+        let actions = block_on(handler.handle(Arc::new(ConnectionContext::new()), args));
+
+        assert_eq!(actions.len(), 1);
+        match &actions[0] {
+            Action::SET_VAR { var_value, .. } => assert_eq!(var_value, &TypedData::UINT32(1)),
+            _ => panic!("should SET_VAR"),
+        }
+
+        Ok(())
+    }
+}
@@ -25,7 +25,7 @@ impl TryFrom<&mut Bytes> for NBArgs {
     type Error = NBArgsParseError;
 
     fn try_from(bytes: &mut Bytes) -> Result<Self, NBArgsParseError> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(NBArgsParseError::InsufficientBytes);
         }
         let b = bytes.split_to(1);
@@ -36,6 +36,5 @@ impl TryFrom<&mut Bytes> for NBArgs {
 impl NBArgs {
     pub fn write_to(self, buf: &mut BytesMut) {
         buf.put_u8(self.0);
-        ()
     }
 }
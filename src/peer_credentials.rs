@@ -0,0 +1,110 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+// The process at the other end of a unix socket, as reported by SO_PEERCRED.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct PeerCredentials {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PeerCredentials {
+    // Any unix stream, e.g. from std or from the runtime.
+    #[cfg(target_os = "linux")]
+    pub fn from_unix_stream<S: AsRawFd>(stream: &S) -> io::Result<Self> {
+        use std::mem;
+
+        let mut ucred: libc::ucred = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let r = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut ucred as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if r == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            pid: ucred.pid as u32,
+            uid: ucred.uid,
+            gid: ucred.gid,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn from_unix_stream<S: AsRawFd>(_stream: &S) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "SO_PEERCRED is only supported on linux",
+        ))
+    }
+}
+
+// Peers allowed to connect to the unix listeners, any matching uid, gid or pid is enough.
+// Everyone is allowed when all of them are empty.
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct AllowedPeers {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+    pub pids: Vec<u32>,
+}
+
+impl AllowedPeers {
+    pub fn is_empty(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty() && self.pids.is_empty()
+    }
+
+    pub fn allows(&self, peer: &PeerCredentials) -> bool {
+        self.is_empty()
+            || self.uids.contains(&peer.uid)
+            || self.gids.contains(&peer.gid)
+            || self.pids.contains(&peer.pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::process;
+
+    #[test]
+    fn test_from_unix_stream() -> anyhow::Result<()> {
+        let (stream, _) = UnixStream::pair()?;
+        let peer = PeerCredentials::from_unix_stream(&stream)?;
+        assert_eq!(peer.pid, process::id());
+        assert_eq!(peer.uid, unsafe { libc::getuid() });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allows() -> anyhow::Result<()> {
+        let peer = PeerCredentials {
+            pid: 100,
+            uid: 99,
+            gid: 98,
+        };
+
+        assert!(AllowedPeers::default().allows(&peer));
+        assert!(AllowedPeers {
+            gids: vec![1, 98],
+            ..Default::default()
+        }
+        .allows(&peer));
+        assert!(!AllowedPeers {
+            uids: vec![0],
+            pids: vec![1],
+            ..Default::default()
+        }
+        .allows(&peer));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,9 @@
+// The executor and the listeners, each behind the feature of the same name. The
+// protocol core, from `Frame` to the connection loop, does not depend on either.
+#[cfg(feature = "smol")]
+pub mod smol;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+#[cfg(not(any(feature = "smol", feature = "tokio")))]
+compile_error!("either the smol or the tokio feature is required");
@@ -0,0 +1,239 @@
+use crate::agent::{self, accept_failed, is_peer_allowed, spawn_connection, Runtime};
+use crate::{client_subject, AgentState, ConnectionContext, Listener, ListenerSocket};
+use anyhow::anyhow;
+use futures::channel::oneshot;
+use futures::future::{try_join_all, BoxFuture};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::{pin_mut, select, FutureExt};
+use futures_rustls::TlsAcceptor;
+use log::*;
+use smol::{Async, Task};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+pub(crate) struct Smol;
+
+impl Runtime for Smol {
+    fn spawn<T: Send + 'static>(future: BoxFuture<'static, T>) -> BoxFuture<'static, T> {
+        Task::spawn(future).boxed()
+    }
+
+    fn spawn_detached(future: BoxFuture<'static, ()>) {
+        Task::spawn(future).detach()
+    }
+}
+
+// Runs `future` to completion on the current thread while `threads` executor threads run
+// the tasks spawned with `Task::spawn`, spreading them with smol's work-stealing executor.
+// A single thread runs everything on the current one.
+pub fn run<T>(threads: usize, future: impl Future<Output = T>) -> io::Result<T> {
+    if threads <= 1 {
+        return Ok(smol::run(future));
+    }
+
+    let (stop, stopped) = oneshot::channel::<()>();
+    let stopped = stopped.shared();
+
+    let mut handles = vec![];
+    for i in 0..threads {
+        let stopped = stopped.clone();
+        let handle = thread::Builder::new()
+            .name(format!("executor-{}", i))
+            .spawn(move || smol::run(stopped))?;
+        handles.push(handle);
+    }
+
+    let output = smol::block_on(future);
+
+    drop(stop);
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    Ok(output)
+}
+
+// Serves every listener until one of them fails or the shutdown is triggered, in which
+// case it returns once every connection is drained.
+pub async fn serve(agent_state: Arc<AgentState>, listeners: Vec<Listener>) -> anyhow::Result<()> {
+    try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| accept_loop(listener, agent_state.clone())),
+    )
+    .await?;
+
+    info!("waiting for connections to be drained");
+    agent_state.shutdown().drained().await;
+
+    Ok(())
+}
+
+pub async fn accept_loop(listener: Listener, agent_state: Arc<AgentState>) -> anyhow::Result<()> {
+    let shutdown = agent_state.shutdown().requested().fuse();
+    pin_mut!(shutdown);
+
+    let addr = listener.addr().clone();
+    match listener.into_socket() {
+        ListenerSocket::Tcp(listener) => {
+            let listener = Async::new(listener)?;
+            info!("Listening on {}", addr);
+
+            loop {
+                let (stream, peer_addr) = select! {
+                    r = listener.accept().fuse() => match r {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            accept_failed(e, &addr).await?;
+                            continue;
+                        }
+                    },
+                    _ = shutdown => break,
+                };
+                info!("Accepted client: {:?} on {}", peer_addr, addr);
+
+                if let Err(e) = stream.get_ref().set_nodelay(true) {
+                    warn!("set TCP_NODELAY for {:?} failed: {}", peer_addr, e);
+                    continue;
+                }
+                let state = agent_state.clone();
+                match &agent_state.config().tls {
+                    Some(tls) => {
+                        let acceptor = TlsAcceptor::from(tls.server_config());
+                        let connection = async move {
+                            let stream = acceptor.accept(stream).await.map_err(|e| {
+                                anyhow!("TLS handshake with {:?} failed: {}", peer_addr, e)
+                            })?;
+                            let context = ConnectionContext {
+                                client_subject: client_subject(stream.get_ref().1),
+                                ..Default::default()
+                            };
+                            connection_loop(stream, state, context).await
+                        };
+                        spawn_connection::<Smol, _>(connection, &agent_state);
+                    }
+                    None => spawn_connection::<Smol, _>(
+                        connection_loop(stream, state, ConnectionContext::new()),
+                        &agent_state,
+                    ),
+                }
+            }
+        }
+        ListenerSocket::Unix(listener) => {
+            let listener = Async::new(listener)?;
+            info!("Listening on {}", addr);
+
+            loop {
+                let (stream, peer_addr) = select! {
+                    r = listener.accept().fuse() => match r {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            accept_failed(e, &addr).await?;
+                            continue;
+                        }
+                    },
+                    _ = shutdown => break,
+                };
+                info!("Accepted client: {:?} on {}", peer_addr, addr);
+
+                if !is_peer_allowed(stream.get_ref(), &agent_state, &addr) {
+                    continue;
+                }
+
+                spawn_connection::<Smol, _>(
+                    connection_loop(stream, agent_state.clone(), ConnectionContext::new()),
+                    &agent_state,
+                );
+            }
+        }
+    }
+
+    info!("Stop listening on {}", addr);
+
+    Ok(())
+}
+
+pub async fn connection_loop<S>(
+    stream: S,
+    agent_state: Arc<AgentState>,
+    context: ConnectionContext,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    agent::connection_loop::<Smol, _>(stream, agent_state, context).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Barrier;
+
+    fn write_frame(stream: &mut UnixStream, frame: &[u8]) -> io::Result<()> {
+        stream.write_all(&(frame.len() as u32).to_be_bytes())?;
+        stream.write_all(frame)
+    }
+
+    fn read_frame(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+
+    #[test]
+    fn test_run() -> anyhow::Result<()> {
+        // Only returns if the tasks run on 3 threads at the same time.
+        let barrier = Arc::new(Barrier::new(3));
+        let tasks = (0..3)
+            .map(|_| {
+                let barrier = barrier.clone();
+                Task::spawn(async move {
+                    barrier.wait();
+                    thread::current().id()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let thread_ids = run(3, futures::future::join_all(tasks))?;
+        assert_eq!(thread_ids.len(), 3);
+        assert!(thread_ids.iter().all(|x| *x != thread::current().id()));
+
+        assert_eq!(run(1, async { 1 })?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_loop() -> anyhow::Result<()> {
+        let (server, mut client) = UnixStream::pair()?;
+
+        let client = thread::spawn(move || -> io::Result<Vec<Vec<u8>>> {
+            write_frame(&mut client, b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x10pipelining,async\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57")?;
+            let agent_hello = read_frame(&mut client)?;
+            write_frame(
+                &mut client,
+                b"\x02\0\0\0\x01\0\0\x0bstatus-code\x03\x00\x07message\x08\x02ok",
+            )?;
+            let agent_disconnect = read_frame(&mut client)?;
+            Ok(vec![agent_hello, agent_disconnect])
+        });
+
+        run(1, async move {
+            let stream = Async::new(server)?;
+            connection_loop(stream, Default::default(), ConnectionContext::new()).await
+        })??;
+
+        let frames = client.join().unwrap()?;
+        // AGENT-HELLO then AGENT-DISCONNECT
+        assert_eq!(frames[0][0], 101);
+        assert_eq!(frames[1][0], 102);
+
+        Ok(())
+    }
+}
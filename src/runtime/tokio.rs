@@ -0,0 +1,230 @@
+use crate::agent::{self, accept_failed, is_peer_allowed, spawn_connection, Runtime};
+use crate::{client_subject, AgentState, ConnectionContext, Listener, ListenerSocket};
+use anyhow::anyhow;
+use futures::future::{try_join_all, BoxFuture};
+use futures::{pin_mut, select, FutureExt};
+use log::*;
+use std::future::Future;
+use std::io;
+use std::panic;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::runtime::Builder;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+pub(crate) struct Tokio;
+
+impl Runtime for Tokio {
+    fn spawn<T: Send + 'static>(future: BoxFuture<'static, T>) -> BoxFuture<'static, T> {
+        AbortOnDrop(tokio::spawn(future)).boxed()
+    }
+
+    fn spawn_detached(future: BoxFuture<'static, ()>) {
+        tokio::spawn(future);
+    }
+}
+
+// Unlike smol, tokio keeps running a task whose handle is dropped.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Future for AbortOnDrop<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(&mut self.0).poll(cx).map(|r| match r {
+            Ok(output) => output,
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => panic!("task failed: {}", e),
+        })
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// Runs `future` to completion on a tokio runtime with `threads` worker threads, a single
+// thread runs everything on the current one. Applications with their own runtime call
+// `serve` from it instead.
+pub fn run<T>(threads: usize, future: impl Future<Output = T>) -> io::Result<T> {
+    let runtime = if threads <= 1 {
+        Builder::new_current_thread().enable_all().build()?
+    } else {
+        Builder::new_multi_thread()
+            .worker_threads(threads)
+            .enable_all()
+            .build()?
+    };
+
+    Ok(runtime.block_on(future))
+}
+
+// Serves every listener until one of them fails or the shutdown is triggered, in which
+// case it returns once every connection is drained. Must be called from a tokio runtime.
+pub async fn serve(agent_state: Arc<AgentState>, listeners: Vec<Listener>) -> anyhow::Result<()> {
+    try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| accept_loop(listener, agent_state.clone())),
+    )
+    .await?;
+
+    info!("waiting for connections to be drained");
+    agent_state.shutdown().drained().await;
+
+    Ok(())
+}
+
+pub async fn accept_loop(listener: Listener, agent_state: Arc<AgentState>) -> anyhow::Result<()> {
+    let shutdown = agent_state.shutdown().requested().fuse();
+    pin_mut!(shutdown);
+
+    let addr = listener.addr().clone();
+    match listener.into_socket() {
+        ListenerSocket::Tcp(listener) => {
+            listener.set_nonblocking(true)?;
+            let listener = TcpListener::from_std(listener)?;
+            info!("Listening on {}", addr);
+
+            loop {
+                let (stream, peer_addr) = select! {
+                    r = listener.accept().fuse() => match r {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            accept_failed(e, &addr).await?;
+                            continue;
+                        }
+                    },
+                    _ = shutdown => break,
+                };
+                info!("Accepted client: {:?} on {}", peer_addr, addr);
+
+                if let Err(e) = stream.set_nodelay(true) {
+                    warn!("set TCP_NODELAY for {:?} failed: {}", peer_addr, e);
+                    continue;
+                }
+                let state = agent_state.clone();
+                match &agent_state.config().tls {
+                    Some(tls) => {
+                        let acceptor = TlsAcceptor::from(tls.server_config());
+                        let connection = async move {
+                            let stream = acceptor.accept(stream).await.map_err(|e| {
+                                anyhow!("TLS handshake with {:?} failed: {}", peer_addr, e)
+                            })?;
+                            let context = ConnectionContext {
+                                client_subject: client_subject(stream.get_ref().1),
+                                ..Default::default()
+                            };
+                            connection_loop(stream, state, context).await
+                        };
+                        spawn_connection::<Tokio, _>(connection, &agent_state);
+                    }
+                    None => spawn_connection::<Tokio, _>(
+                        connection_loop(stream, state, ConnectionContext::new()),
+                        &agent_state,
+                    ),
+                }
+            }
+        }
+        ListenerSocket::Unix(listener) => {
+            listener.set_nonblocking(true)?;
+            let listener = UnixListener::from_std(listener)?;
+            info!("Listening on {}", addr);
+
+            loop {
+                let (stream, peer_addr) = select! {
+                    r = listener.accept().fuse() => match r {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            accept_failed(e, &addr).await?;
+                            continue;
+                        }
+                    },
+                    _ = shutdown => break,
+                };
+                info!("Accepted client: {:?} on {}", peer_addr, addr);
+
+                if !is_peer_allowed(&stream, &agent_state, &addr) {
+                    continue;
+                }
+
+                spawn_connection::<Tokio, _>(
+                    connection_loop(stream, agent_state.clone(), ConnectionContext::new()),
+                    &agent_state,
+                );
+            }
+        }
+    }
+
+    info!("Stop listening on {}", addr);
+
+    Ok(())
+}
+
+pub async fn connection_loop<S>(
+    stream: S,
+    agent_state: Arc<AgentState>,
+    context: ConnectionContext,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    agent::connection_loop::<Tokio, _>(stream.compat(), agent_state, context).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net;
+    use std::thread;
+
+    fn write_frame(stream: &mut net::UnixStream, frame: &[u8]) -> io::Result<()> {
+        stream.write_all(&(frame.len() as u32).to_be_bytes())?;
+        stream.write_all(frame)
+    }
+
+    fn read_frame(stream: &mut net::UnixStream) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+
+    #[test]
+    fn test_connection_loop() -> anyhow::Result<()> {
+        let (server, mut client) = net::UnixStream::pair()?;
+
+        let client = thread::spawn(move || -> io::Result<Vec<Vec<u8>>> {
+            write_frame(&mut client, b"\x01\0\0\0\x01\0\0\x12supported-versions\x08\x032.0\x0emax-frame-size\x03\xfc\xf0\x06\x0ccapabilities\x08\x10pipelining,async\tengine-id\x08$6bdec4ec-6b9a-4705-83f4-8817766c0c57")?;
+            let agent_hello = read_frame(&mut client)?;
+            write_frame(
+                &mut client,
+                b"\x02\0\0\0\x01\0\0\x0bstatus-code\x03\x00\x07message\x08\x02ok",
+            )?;
+            let agent_disconnect = read_frame(&mut client)?;
+            Ok(vec![agent_hello, agent_disconnect])
+        });
+
+        run(2, async move {
+            server.set_nonblocking(true)?;
+            let stream = tokio::net::UnixStream::from_std(server)?;
+            connection_loop(stream, Default::default(), ConnectionContext::new()).await
+        })??;
+
+        let frames = client.join().unwrap()?;
+        // AGENT-HELLO then AGENT-DISCONNECT
+        assert_eq!(frames[0][0], 101);
+        assert_eq!(frames[1][0], 102);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use futures::channel::{mpsc, oneshot};
+use futures::future::{FutureExt, Shared};
+use futures::StreamExt;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+
+// Graceful shutdown: the listeners stop accepting once triggered, then every connection
+// drains its pending NOTIFY frames and sends AGENT-DISCONNECT.
+pub struct Shutdown {
+    trigger: Mutex<Option<oneshot::Sender<()>>>,
+    requested: Shared<oneshot::Receiver<()>>,
+    // Cloned into every connection, `drained` completes once all of them are dropped.
+    guard: Mutex<Option<mpsc::Sender<()>>>,
+    guards: Mutex<Option<mpsc::Receiver<()>>>,
+}
+
+// Held by a connection until it is closed.
+pub struct ConnectionGuard {
+    _guard: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (trigger, requested) = oneshot::channel();
+        let (guard, guards) = mpsc::channel(0);
+        Self {
+            trigger: Mutex::new(Some(trigger)),
+            requested: requested.shared(),
+            guard: Mutex::new(Some(guard)),
+            guards: Mutex::new(Some(guards)),
+        }
+    }
+
+    // Returns false if the shutdown was already triggered.
+    pub fn trigger(&self) -> bool {
+        match self.trigger.lock().unwrap().take() {
+            Some(trigger) => trigger.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.trigger.lock().unwrap().is_none()
+    }
+
+    pub fn requested(&self) -> impl Future<Output = ()> {
+        self.requested.clone().map(|_| ())
+    }
+
+    // None once `drained` was called, no connection should be accepted anymore.
+    pub fn connection_guard(&self) -> Option<ConnectionGuard> {
+        self.guard
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|guard| ConnectionGuard {
+                _guard: guard.clone(),
+            })
+    }
+
+    pub async fn drained(&self) {
+        self.guard.lock().unwrap().take();
+
+        let guards = self.guards.lock().unwrap().take();
+        if let Some(mut guards) = guards {
+            while guards.next().await.is_some() {}
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("is_triggered", &self.is_triggered())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::future::{select, Either};
+    use futures::pin_mut;
+
+    #[test]
+    fn test_drained() -> anyhow::Result<()> {
+        let shutdown = Shutdown::new();
+        let guard = shutdown.connection_guard();
+        assert!(guard.is_some());
+
+        assert!(!shutdown.is_triggered());
+        assert!(shutdown.trigger());
+        assert!(!shutdown.trigger());
+        block_on(shutdown.requested());
+
+        {
+            let drained = shutdown.drained();
+            pin_mut!(drained);
+            let pending = futures::future::ready(());
+            match block_on(select(drained, pending)) {
+                Either::Left(_) => panic!("should wait for the connection guard"),
+                Either::Right(((), drained)) => {
+                    drop(guard);
+                    block_on(drained);
+                }
+            }
+        }
+
+        assert!(shutdown.connection_guard().is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::upgrade::spawn_ready;
+use crate::ListenAddr;
+use log::*;
+use std::env;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// Set by the supervisor to the id of each worker process, from 0.
+pub const WORKER_ENV: &str = "SPOA_WORKER";
+// Time before a worker that exited is started again.
+pub const WORKER_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+// The worker id when started by a supervisor.
+pub fn worker_id() -> Option<usize> {
+    env::var(WORKER_ENV).ok()?.parse().ok()
+}
+
+// Runs worker processes with the same arguments, handing each of them the listening
+// sockets. The workers accept from the same sockets, except the TCP listeners with
+// `reuse-port` that every worker binds itself.
+#[derive(Debug)]
+pub struct Supervisor {
+    listen_fds: Vec<(RawFd, ListenAddr)>,
+    // Indexed by worker id, None while the worker is not running.
+    pids: Mutex<Vec<Option<libc::pid_t>>>,
+    stopping: AtomicBool,
+    // The current executable with the same arguments.
+    worker_command: fn() -> io::Result<Command>,
+}
+
+fn current_exe_command() -> io::Result<Command> {
+    let mut command = Command::new(env::current_exe()?);
+    command.args(env::args_os().skip(1));
+    Ok(command)
+}
+
+impl Supervisor {
+    pub fn new(workers: usize, listen_fds: Vec<(RawFd, ListenAddr)>) -> Self {
+        Self {
+            listen_fds,
+            pids: Mutex::new(vec![None; workers]),
+            stopping: AtomicBool::new(false),
+            worker_command: current_exe_command,
+        }
+    }
+
+    // Returns once every worker has taken over the listeners.
+    pub fn start(&self) -> anyhow::Result<()> {
+        let workers = self.pids.lock().unwrap().len();
+        for id in 0..workers {
+            self.spawn(id)?;
+        }
+
+        Ok(())
+    }
+
+    fn spawn(&self, id: usize) -> anyhow::Result<()> {
+        let mut command = (self.worker_command)()?;
+        command.env(WORKER_ENV, id.to_string());
+        // The workers are stopped along with the supervisor, even if it is killed.
+        #[cfg(target_os = "linux")]
+        unsafe {
+            command.pre_exec(|| {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = spawn_ready(command, &self.listen_fds)?;
+        let pid = child.id() as libc::pid_t;
+        info!("worker {}: started pid {}", id, pid);
+
+        let mut pids = self.pids.lock().unwrap();
+        pids[id] = Some(pid);
+        if self.stopping.load(Ordering::SeqCst) {
+            unsafe { libc::kill(pid, libc::SIGTERM) };
+        }
+
+        Ok(())
+    }
+
+    // Forwards `signal` to every running worker.
+    pub fn signal(&self, signal: libc::c_int) {
+        let pids = self.pids.lock().unwrap();
+        for pid in pids.iter().flatten() {
+            unsafe { libc::kill(*pid, signal) };
+        }
+    }
+
+    // Forwards `signal` to every worker, which are no longer restarted once they exit.
+    // Returns whether it is the first call.
+    pub fn stop(&self, signal: libc::c_int) -> bool {
+        let pids = self.pids.lock().unwrap();
+        let first = !self.stopping.swap(true, Ordering::SeqCst);
+        for pid in pids.iter().flatten() {
+            unsafe { libc::kill(*pid, signal) };
+        }
+
+        first
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    // Restarts the workers that exit until `stop` is called, then returns once every
+    // worker has exited.
+    pub fn wait(&self) -> io::Result<()> {
+        loop {
+            if self.is_stopping() && self.pids.lock().unwrap().iter().all(Option::is_none) {
+                return Ok(());
+            }
+
+            let mut status = 0;
+            let pid = unsafe { libc::waitpid(-1, &mut status, 0) };
+            if pid == -1 {
+                let e = io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    Some(libc::ECHILD) => return Ok(()),
+                    _ => return Err(e),
+                }
+            }
+
+            // Other children, e.g. the new process on upgrade, are not restarted.
+            let id = {
+                let mut pids = self.pids.lock().unwrap();
+                match pids.iter().position(|x| *x == Some(pid)) {
+                    Some(id) => {
+                        pids[id] = None;
+                        id
+                    }
+                    None => continue,
+                }
+            };
+
+            if libc::WIFSIGNALED(status) {
+                warn!(
+                    "worker {}: pid {} killed by signal {}",
+                    id,
+                    pid,
+                    libc::WTERMSIG(status)
+                );
+            } else {
+                info!(
+                    "worker {}: pid {} exited with status {}",
+                    id,
+                    pid,
+                    libc::WEXITSTATUS(status)
+                );
+            }
+
+            while !self.is_stopping() {
+                thread::sleep(WORKER_RESTART_DELAY);
+                if self.is_stopping() {
+                    break;
+                }
+                match self.spawn(id) {
+                    Ok(_) => break,
+                    Err(e) => error!("worker {}: restart failed: {:#}", id, e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UPGRADE_READY_FD_ENV;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    // Reports ready like the agent does, then runs until it is killed. Unlike dash, bash
+    // redirects to file descriptors above 9.
+    fn ready_command() -> io::Result<Command> {
+        let mut command = Command::new("bash");
+        command.arg("-c").arg(format!(
+            "printf 1 >&${}; exec sleep 30",
+            UPGRADE_READY_FD_ENV
+        ));
+        Ok(command)
+    }
+
+    fn running_pid(supervisor: &Supervisor) -> Option<libc::pid_t> {
+        supervisor.pids.lock().unwrap()[0]
+    }
+
+    #[test]
+    fn test_restart_and_stop() -> anyhow::Result<()> {
+        let supervisor = Arc::new(Supervisor {
+            worker_command: ready_command,
+            ..Supervisor::new(1, vec![])
+        });
+        supervisor.start()?;
+        let pid = running_pid(&supervisor).unwrap();
+
+        let waiting = {
+            let supervisor = supervisor.clone();
+            thread::spawn(move || supervisor.wait())
+        };
+
+        // A worker that dies is started again.
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        let started = Instant::now();
+        let restarted_pid = loop {
+            match running_pid(&supervisor) {
+                Some(x) if x != pid => break x,
+                _ if started.elapsed() > WORKER_RESTART_DELAY * 5 => panic!("not restarted"),
+                _ => thread::sleep(Duration::from_millis(50)),
+            }
+        };
+
+        assert!(supervisor.stop(libc::SIGTERM));
+        assert!(!supervisor.stop(libc::SIGTERM));
+        waiting.join().unwrap()?;
+        assert_eq!(running_pid(&supervisor), None);
+        // The worker was reaped.
+        assert_eq!(unsafe { libc::kill(restarted_pid, 0) }, -1);
+
+        Ok(())
+    }
+}
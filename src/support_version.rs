@@ -1,6 +1,9 @@
 use semver::Version;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
 pub struct SupportVersion(Version);
 
 impl SupportVersion {
@@ -18,8 +21,22 @@ impl SupportVersion {
     }
 }
 
-impl ToString for SupportVersion {
-    fn to_string(&self) -> String {
-        format!("{}.{}", self.0.major, self.0.minor)
+#[derive(Error, PartialEq, Debug)]
+#[error("invalid version {0}")]
+pub struct SupportVersionParseError(String);
+
+impl FromStr for SupportVersion {
+    type Err = SupportVersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, SupportVersionParseError> {
+        Self::parse(s).ok_or_else(|| SupportVersionParseError(s.to_owned()))
+    }
+}
+
+impl_deserialize_from_str!(SupportVersion);
+
+impl fmt::Display for SupportVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.0.major, self.0.minor)
     }
 }
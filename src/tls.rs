@@ -0,0 +1,212 @@
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{VerifierBuilderError, WebPkiClientVerifier};
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+// TLS on the TCP listeners, the files are read again on every reload so that renewed
+// certificates are picked up on SIGHUP.
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    // Client certificates signed by these CAs are required when set.
+    pub client_ca_path: Option<PathBuf>,
+    server_config: Arc<ServerConfig>,
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("read {} failed: {1}", .0.display())]
+    Io(PathBuf, io::Error),
+    #[error("no certificate in {}", .0.display())]
+    NoCertificate(PathBuf),
+    #[error("no private key in {}", .0.display())]
+    NoPrivateKey(PathBuf),
+    #[error("invalid client CA {}: {1}", .0.display())]
+    InvalidClientCa(PathBuf, String),
+    #[error("invalid certificate or key: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+impl TlsServerConfig {
+    pub fn load(
+        cert_path: &Path,
+        key_path: &Path,
+        client_ca_path: Option<&Path>,
+    ) -> Result<Self, TlsError> {
+        let provider = Arc::new(ring::default_provider());
+
+        let certs = read_certs(cert_path)?;
+        let key = read_private_key(key_path)?;
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match client_ca_path {
+            Some(client_ca_path) => {
+                let client_verifier = client_verifier(client_ca_path, provider)
+                    .map_err(|e| TlsError::InvalidClientCa(client_ca_path.to_owned(), e))?;
+                builder.with_client_cert_verifier(client_verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server_config = builder.with_single_cert(certs, key)?;
+
+        Ok(Self {
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+            client_ca_path: client_ca_path.map(|x| x.to_owned()),
+            server_config: Arc::new(server_config),
+        })
+    }
+
+    // Shared by the TLS acceptor of every runtime.
+    pub fn server_config(&self) -> Arc<ServerConfig> {
+        self.server_config.clone()
+    }
+}
+
+impl fmt::Debug for TlsServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsServerConfig")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .field("client_ca_path", &self.client_ca_path)
+            .finish()
+    }
+}
+
+// The subject of the verified client certificate, e.g. `CN=haproxy, O=example`
+pub fn client_subject(connection: &ServerConnection) -> Option<String> {
+    let cert = connection.peer_certificates()?.first()?;
+    let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+
+    Some(cert.subject().to_string())
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let file = File::open(path).map_err(|e| TlsError::Io(path.to_owned(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::Io(path.to_owned(), e))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate(path.to_owned()));
+    }
+
+    Ok(certs)
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let file = File::open(path).map_err(|e| TlsError::Io(path.to_owned(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| TlsError::Io(path.to_owned(), e))?
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_owned()))
+}
+
+fn client_verifier(
+    path: &Path,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn rustls::server::danger::ClientCertVerifier>, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(path).map_err(|e| e.to_string())? {
+        roots.add(cert).map_err(|e| e.to_string())?;
+    }
+
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(|e: VerifierBuilderError| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection};
+    use std::convert::TryFrom;
+    use std::fs;
+
+    #[test]
+    fn test_client_subject() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let ca_key = KeyPair::generate()?;
+        let mut ca_params = CertificateParams::new(vec![])?;
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "spoa test CA");
+        let ca_cert = ca_params.self_signed(&ca_key)?;
+        fs::write(dir.path().join("ca.pem"), ca_cert.pem())?;
+
+        let server_key = KeyPair::generate()?;
+        let server_cert = CertificateParams::new(vec!["localhost".to_owned()])?.signed_by(
+            &server_key,
+            &ca_cert,
+            &ca_key,
+        )?;
+        fs::write(dir.path().join("cert.pem"), server_cert.pem())?;
+        fs::write(dir.path().join("key.pem"), server_key.serialize_pem())?;
+
+        let client_key = KeyPair::generate()?;
+        let mut client_params = CertificateParams::new(vec![])?;
+        client_params
+            .distinguished_name
+            .push(DnType::CommonName, "haproxy");
+        let client_cert = client_params.signed_by(&client_key, &ca_cert, &ca_key)?;
+
+        let tls = TlsServerConfig::load(
+            &dir.path().join("cert.pem"),
+            &dir.path().join("key.pem"),
+            Some(&dir.path().join("ca.pem")),
+        )?;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca_cert.der().clone())?;
+        let client_config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                vec![client_cert.der().clone()],
+                PrivateKeyDer::try_from(client_key.serialize_der()).map_err(anyhow::Error::msg)?,
+            )?;
+
+        // Handshake in memory.
+        let mut server = ServerConnection::new(tls.server_config())?;
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), ServerName::try_from("localhost")?)?;
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut buf = vec![];
+            client.write_tls(&mut buf)?;
+            if !buf.is_empty() {
+                server.read_tls(&mut &buf[..])?;
+            }
+            server.process_new_packets()?;
+
+            let mut buf = vec![];
+            server.write_tls(&mut buf)?;
+            if !buf.is_empty() {
+                client.read_tls(&mut &buf[..])?;
+            }
+            client.process_new_packets()?;
+        }
+        assert_eq!(client_subject(&server), Some("CN=haproxy".to_owned()));
+
+        assert!(matches!(
+            TlsServerConfig::load(
+                &dir.path().join("key.pem"),
+                &dir.path().join("key.pem"),
+                None
+            ),
+            Err(TlsError::NoCertificate(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -96,6 +96,7 @@ impl TypedData {
 
 #[derive(IntoPrimitive, TryFromPrimitive, PartialEq, Debug)]
 #[repr(u8)]
+#[allow(clippy::upper_case_acronyms)]
 enum TypedDataType {
     NULL = 0,
     BOOL = 1,
@@ -123,6 +124,7 @@ impl From<VarintParseError> for TypedDataParseError {
     fn from(e: VarintParseError) -> Self {
         match e {
             VarintParseError::InsufficientBytes => Self::InsufficientBytes,
+            VarintParseError::Overflow => Self::Invalid,
         }
     }
 }
@@ -148,7 +150,7 @@ impl TryFrom<&mut Bytes> for TypedData {
     type Error = TypedDataParseError;
 
     fn try_from(bytes: &mut Bytes) -> Result<Self, TypedDataParseError> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(TypedDataParseError::InsufficientBytes);
         }
         let b = bytes.split_to(1);
@@ -159,21 +161,21 @@ impl TryFrom<&mut Bytes> for TypedData {
             TypedDataType::NULL => Self::NULL,
             TypedDataType::BOOL => Self::BOOL(b[0] & 0x10_u8 == 0x10_u8),
             TypedDataType::INT32 => {
-                let varint = Varint::try_from(bytes).map_err(|e| TypedDataParseError::from(e))?;
+                let varint = Varint::try_from(bytes).map_err(TypedDataParseError::from)?;
                 let val = varint.i32_val().ok_or(TypedDataParseError::Invalid)?;
                 Self::INT32(val)
             }
             TypedDataType::UINT32 => {
-                let varint = Varint::try_from(bytes).map_err(|e| TypedDataParseError::from(e))?;
+                let varint = Varint::try_from(bytes).map_err(TypedDataParseError::from)?;
                 let val = varint.u32_val().ok_or(TypedDataParseError::Invalid)?;
                 Self::UINT32(val)
             }
             TypedDataType::INT64 => {
-                let varint = Varint::try_from(bytes).map_err(|e| TypedDataParseError::from(e))?;
+                let varint = Varint::try_from(bytes).map_err(TypedDataParseError::from)?;
                 Self::INT64(varint.i64_val())
             }
             TypedDataType::UINT64 => {
-                let varint = Varint::try_from(bytes).map_err(|e| TypedDataParseError::from(e))?;
+                let varint = Varint::try_from(bytes).map_err(TypedDataParseError::from)?;
                 Self::UINT64(varint.u64_val())
             }
             TypedDataType::IPV4 => {
@@ -197,12 +199,12 @@ impl TryFrom<&mut Bytes> for TypedData {
             }
             TypedDataType::STRING => {
                 let varint_string =
-                    VarintString::try_from(bytes).map_err(|e| TypedDataParseError::from(e))?;
+                    VarintString::try_from(bytes).map_err(TypedDataParseError::from)?;
                 Self::STRING(varint_string)
             }
             TypedDataType::BINARY => {
                 let varint_binary =
-                    VarintBinary::try_from(bytes).map_err(|e| TypedDataParseError::from(e))?;
+                    VarintBinary::try_from(bytes).map_err(TypedDataParseError::from)?;
                 Self::BINARY(varint_binary)
             }
         };
@@ -215,26 +217,26 @@ impl TypedData {
     pub fn write_to(&self, buf: &mut BytesMut) {
         match self {
             TypedData::NULL => buf.put_u8(0),
-            TypedData::BOOL(val) => buf.put_u8(if true == *val {
+            TypedData::BOOL(val) => buf.put_u8(if *val {
                 0b_0001_0001_u8
             } else {
                 0b_0000_0001_u8
             }),
             TypedData::INT32(val) => {
                 buf.put_u8(0b_0000_0010_u8);
-                buf.extend_from_slice(BytesMut::from(Varint::from(*val as u32)).as_ref());
+                Varint::from(*val as u32).write_to(buf);
             }
             TypedData::UINT32(val) => {
                 buf.put_u8(0b_0000_0011_u8);
-                buf.extend_from_slice(BytesMut::from(Varint::from(*val)).as_ref());
+                Varint::from(*val).write_to(buf);
             }
             TypedData::INT64(val) => {
                 buf.put_u8(0b_0000_0100_u8);
-                buf.extend_from_slice(BytesMut::from(Varint::from(*val as u64)).as_ref());
+                Varint::from(*val as u64).write_to(buf);
             }
             TypedData::UINT64(val) => {
                 buf.put_u8(0b_0000_0101_u8);
-                buf.extend_from_slice(BytesMut::from(Varint::from(*val)).as_ref());
+                Varint::from(*val).write_to(buf);
             }
             TypedData::IPV4(val) => {
                 buf.put_u8(0b_0000_0110_u8);
@@ -253,7 +255,6 @@ impl TypedData {
                 val.write_to(buf);
             }
         }
-        ()
     }
 }
 
@@ -330,18 +331,15 @@ mod tests {
             TypedData::IPV6(Ipv6Addr::new(1, 1, 1, 1, 1, 1, 1, 1))
         );
 
-        let mut bytes = Bytes::from_static(&[0b_0000_1000_u8, 0x01, 'a' as u8]);
+        let mut bytes = Bytes::from_static(&[0b_0000_1000_u8, 0x01, b'a']);
         let bytes = &mut bytes;
         let typed_data: TypedData = bytes.try_into()?;
         assert_eq!(typed_data, TypedData::STRING(VarintString::new("a")));
 
-        let mut bytes = Bytes::from_static(&[0b_0000_1001_u8, 0x01, 'a' as u8]);
+        let mut bytes = Bytes::from_static(&[0b_0000_1001_u8, 0x01, b'a']);
         let bytes = &mut bytes;
         let typed_data: TypedData = bytes.try_into()?;
-        assert_eq!(
-            typed_data,
-            TypedData::BINARY(VarintBinary::new(&vec!['a' as u8]))
-        );
+        assert_eq!(typed_data, TypedData::BINARY(VarintBinary::new(b"a")));
 
         Ok(())
     }
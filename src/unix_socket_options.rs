@@ -0,0 +1,118 @@
+use std::ffi::CString;
+use std::fs::{self, Permissions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::{chown, PermissionsExt};
+use std::path::Path;
+use std::ptr;
+
+// Applied to the socket files of `unix@` listeners once bound, like the HAProxy `bind`
+// options of the same names.
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct UnixSocketOptions {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl UnixSocketOptions {
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        if self.uid.is_some() || self.gid.is_some() {
+            chown(path, self.uid, self.gid)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Octal, e.g. `660` or `0660`
+pub fn parse_mode(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 8).ok().filter(|x| *x <= 0o7777)
+}
+
+// A user name or a numeric uid.
+pub fn lookup_uid(user: &str) -> Option<u32> {
+    if let Ok(uid) = user.parse() {
+        return Some(uid);
+    }
+    let name = CString::new(user).ok()?;
+
+    let mut buf_len = 1024;
+    loop {
+        let mut buf = vec![0 as libc::c_char; buf_len];
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let r = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match r {
+            libc::ERANGE => buf_len *= 2,
+            0 if !result.is_null() => return Some(passwd.pw_uid),
+            _ => return None,
+        }
+    }
+}
+
+// A group name or a numeric gid.
+pub fn lookup_gid(group: &str) -> Option<u32> {
+    if let Ok(gid) = group.parse() {
+        return Some(gid);
+    }
+    let name = CString::new(group).ok()?;
+
+    let mut buf_len = 1024;
+    loop {
+        let mut buf = vec![0 as libc::c_char; buf_len];
+        let mut group: libc::group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let r = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match r {
+            libc::ERANGE => buf_len *= 2,
+            0 if !result.is_null() => return Some(group.gr_gid),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() -> anyhow::Result<()> {
+        assert_eq!(parse_mode("660"), Some(0o660));
+        assert_eq!(parse_mode("0600"), Some(0o600));
+        assert_eq!(parse_mode("680"), None);
+        assert_eq!(parse_mode("17777"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup() -> anyhow::Result<()> {
+        assert_eq!(lookup_uid("root"), Some(0));
+        assert_eq!(lookup_uid("1000"), Some(1000));
+        assert_eq!(lookup_uid("no-such-user"), None);
+        assert_eq!(lookup_gid("root"), Some(0));
+        assert_eq!(lookup_gid("no-such-group"), None);
+
+        Ok(())
+    }
+}
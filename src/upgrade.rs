@@ -0,0 +1,88 @@
+use crate::{format_listen_fds, ListenAddr, LISTEN_FDS_ENV};
+use anyhow::{anyhow, bail};
+use log::*;
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+// Set by the previous process on upgrade, written to once the listeners are taken over.
+pub const UPGRADE_READY_FD_ENV: &str = "SPOA_UPGRADE_READY_FD";
+pub const UPGRADE_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Starts the current executable with the same arguments, handing it the listening sockets
+// across exec. Returns once the new process is ready, the caller can then drain its own
+// connections and exit. The new process is killed if it does not get ready in time.
+pub fn upgrade(listen_fds: &[(RawFd, ListenAddr)]) -> anyhow::Result<Child> {
+    let mut command = Command::new(env::current_exe()?);
+    command.args(env::args_os().skip(1));
+
+    let child = spawn_ready(command, listen_fds)?;
+    info!("upgrade: started pid {}", child.id());
+
+    Ok(child)
+}
+
+// Spawns `command` with the listening sockets and waits until it calls `notify_upgrade_ready`.
+pub(crate) fn spawn_ready(
+    mut command: Command,
+    listen_fds: &[(RawFd, ListenAddr)],
+) -> anyhow::Result<Child> {
+    let (mut ready, ready_child) = UnixStream::pair()?;
+    ready.set_read_timeout(Some(UPGRADE_READY_TIMEOUT))?;
+
+    let ready_fd = ready_child.as_raw_fd();
+    let mut inherited_fds: Vec<RawFd> = listen_fds.iter().map(|(fd, _)| *fd).collect();
+    inherited_fds.push(ready_fd);
+
+    command
+        .env(LISTEN_FDS_ENV, format_listen_fds(listen_fds))
+        .env(UPGRADE_READY_FD_ENV, ready_fd.to_string());
+    // Every fd is opened with FD_CLOEXEC by std, only the handed over ones survive exec.
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &inherited_fds {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn()?;
+    drop(ready_child);
+
+    let mut buf = [0; 1];
+    let e = match ready.read(&mut buf) {
+        Ok(1) => return Ok(child),
+        Ok(_) => anyhow!("pid {} exited before being ready", child.id()),
+        Err(e) => anyhow!("pid {} not ready: {}", child.id(), e),
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+
+    Err(e)
+}
+
+// Tells the previous process, if any, that the listeners are taken over.
+pub fn notify_upgrade_ready() -> anyhow::Result<()> {
+    let fd = match env::var(UPGRADE_READY_FD_ENV) {
+        Ok(fd) => fd,
+        Err(_) => return Ok(()),
+    };
+    env::remove_var(UPGRADE_READY_FD_ENV);
+    env::remove_var(LISTEN_FDS_ENV);
+
+    let fd = match fd.parse::<RawFd>() {
+        Ok(fd) => fd,
+        Err(_) => bail!("invalid {} {}", UPGRADE_READY_FD_ENV, fd),
+    };
+    let mut ready = unsafe { UnixStream::from_raw_fd(fd) };
+    ready.write_all(b"1")?;
+
+    Ok(())
+}
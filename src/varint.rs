@@ -50,6 +50,8 @@ impl Varint {
 pub enum VarintParseError {
     #[error("Insufficient bytes")]
     InsufficientBytes,
+    #[error("Overflow")]
+    Overflow,
 }
 
 impl TryFrom<&mut Bytes> for Varint {
@@ -58,7 +60,7 @@ impl TryFrom<&mut Bytes> for Varint {
     fn try_from(bytes: &mut Bytes) -> Result<Self, VarintParseError> {
         let mut _n: u8 = 0;
 
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(VarintParseError::InsufficientBytes);
         }
         let b = bytes.split_to(1);
@@ -71,14 +73,20 @@ impl TryFrom<&mut Bytes> for Varint {
             let mut _r: u8 = 4;
 
             loop {
-                if bytes.len() < 1 {
+                if bytes.is_empty() {
                     return Err(VarintParseError::InsufficientBytes);
                 }
                 let b = bytes.split_to(1);
                 _n += 1;
                 let _val_u8 = b[0];
 
-                val_u64 += (_val_u8 as u64) << _r;
+                // At most 10 bytes for a u64, anything longer is garbage.
+                if _r >= 64 {
+                    return Err(VarintParseError::Overflow);
+                }
+                val_u64 = val_u64
+                    .checked_add((_val_u8 as u64) << _r)
+                    .ok_or(VarintParseError::Overflow)?;
 
                 _r += 7;
 
@@ -88,7 +96,7 @@ impl TryFrom<&mut Bytes> for Varint {
             }
         }
 
-        if val_u64 <= (u32::max_value() as u64) {
+        if val_u64 <= (u32::MAX as u64) {
             Ok((val_u64 as u32).into())
         } else {
             Ok(val_u64.into())
@@ -99,27 +107,47 @@ impl TryFrom<&mut Bytes> for Varint {
 impl From<Varint> for BytesMut {
     fn from(varint: Varint) -> Self {
         let mut buf = BytesMut::new();
+        varint.write_to(&mut buf);
+        buf
+    }
+}
 
-        let val_u64 = varint.u64_val();
+impl Varint {
+    pub fn write_to(&self, buf: &mut BytesMut) {
+        let val_u64 = self.u64_val();
 
         if val_u64 < 240 {
             buf.put_u8(val_u64 as u8)
         } else {
             let mut val_u64 = val_u64;
 
-            buf.put_u8((val_u64 % 256 | 240) as u8);
+            buf.put_u8(((val_u64 % 256) | 240) as u8);
 
             val_u64 = (val_u64 - 240) >> 4;
             while val_u64 >= 128 {
-                buf.put_u8((val_u64 % 256 | 128) as u8);
+                buf.put_u8(((val_u64 % 256) | 128) as u8);
 
                 val_u64 = (val_u64 - 128) >> 7;
             }
 
             buf.put_u8(val_u64 as u8);
         }
+    }
 
-        buf
+    // The number of bytes `write_to` appends.
+    pub fn encoded_len(&self) -> usize {
+        let mut val_u64 = self.u64_val();
+        if val_u64 < 240 {
+            return 1;
+        }
+
+        let mut len = 2;
+        val_u64 = (val_u64 - 240) >> 4;
+        while val_u64 >= 128 {
+            len += 1;
+            val_u64 = (val_u64 - 128) >> 7;
+        }
+        len
     }
 }
 
@@ -130,17 +158,17 @@ mod tests {
 
     #[test]
     fn test_x_val() -> anyhow::Result<()> {
-        let varint = Varint(VarintStorage::U32(u32::max_value()));
-        assert_eq!(varint.u32_val(), Some(u32::max_value()));
-        assert_eq!(varint.i32_val(), Some(u32::max_value() as i32));
-        assert_eq!(varint.u64_val(), u32::max_value() as u64);
-        assert_eq!(varint.i64_val(), u32::max_value() as i64);
+        let varint = Varint(VarintStorage::U32(u32::MAX));
+        assert_eq!(varint.u32_val(), Some(u32::MAX));
+        assert_eq!(varint.i32_val(), Some(u32::MAX as i32));
+        assert_eq!(varint.u64_val(), u32::MAX as u64);
+        assert_eq!(varint.i64_val(), u32::MAX as i64);
 
-        let varint = Varint(VarintStorage::U64(u64::max_value()));
+        let varint = Varint(VarintStorage::U64(u64::MAX));
         assert_eq!(varint.u32_val(), None);
         assert_eq!(varint.i32_val(), None);
-        assert_eq!(varint.u64_val(), u64::max_value());
-        assert_eq!(varint.i64_val(), u64::max_value() as i64);
+        assert_eq!(varint.u64_val(), u64::MAX);
+        assert_eq!(varint.i64_val(), u64::MAX as i64);
 
         let varint = Varint(VarintStorage::U32(1));
         assert_eq!(varint.u32_val(), Some(1));
@@ -205,17 +233,14 @@ mod tests {
                 ],
             ),
             //
-            (u8::max_value() as u64, vec![0b_11111111_u8, 0]),
+            (u8::MAX as u64, vec![0b_11111111_u8, 0]),
+            (u16::MAX as u64, vec![0b_11111111_u8, 0b_11110000_u8, 30]),
             (
-                u16::max_value() as u64,
-                vec![0b_11111111_u8, 0b_11110000_u8, 30],
-            ),
-            (
-                u32::max_value() as u64,
+                u32::MAX as u64,
                 vec![0b_11111111_u8, 0b_11110000_u8, 254, 254, 126],
             ),
             (
-                u64::max_value(),
+                u64::MAX,
                 vec![
                     0b_11111111_u8,
                     0b_11110000_u8,
@@ -232,12 +257,20 @@ mod tests {
         ];
 
         for (val, bytes) in results {
+            let len = bytes.len();
             let mut bytes = Bytes::from(bytes);
             let bytes = &mut bytes;
             let varint: Varint = bytes.try_into()?;
             assert_eq!(varint.u64_val(), val);
+            assert_eq!(varint.encoded_len(), len);
         }
 
+        let mut bytes = Bytes::from(vec![0b_11111111_u8; 16]);
+        assert_eq!(
+            Varint::try_from(&mut bytes).unwrap_err(),
+            VarintParseError::Overflow
+        );
+
         Ok(())
     }
 }
@@ -3,17 +3,28 @@ use bytes::{BufMut, Bytes, BytesMut};
 use std::convert::{TryFrom, TryInto};
 use thiserror::Error;
 
+// A view into the frame it was read from.
 #[derive(PartialEq, Clone, Debug)]
-pub struct VarintBinary(Vec<u8>);
+pub struct VarintBinary(Bytes);
 
 impl VarintBinary {
-    pub fn new(val: &Vec<u8>) -> Self {
-        Self(val.to_owned())
+    pub fn new(val: &[u8]) -> Self {
+        Self(Bytes::copy_from_slice(val))
     }
 
     pub fn val(&self) -> &[u8] {
         self.0.as_ref()
     }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+impl From<Bytes> for VarintBinary {
+    fn from(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
 }
 
 #[derive(Error, PartialEq, Debug)]
@@ -30,17 +41,11 @@ impl TryFrom<&mut Bytes> for VarintBinary {
             .try_into()
             .map_err(|_| VarintBinaryParseError::InsufficientBytes)?;
         let len = len.u64_val() as usize;
-        let a: Vec<u8> = if len == 0 {
-            vec![]
-        } else {
-            if bytes.len() < len {
-                return Err(VarintBinaryParseError::InsufficientBytes);
-            }
-            let b = bytes.split_to(len);
-            b.to_vec()
-        };
-
-        Ok(Self(a))
+        if bytes.len() < len {
+            return Err(VarintBinaryParseError::InsufficientBytes);
+        }
+
+        Ok(Self(bytes.split_to(len)))
     }
 }
 
@@ -48,10 +53,8 @@ impl VarintBinary {
     pub fn write_to(&self, buf: &mut BytesMut) {
         let len = self.val().len() as u64;
 
-        buf.extend_from_slice(BytesMut::from(Varint::from(len)).as_ref());
+        Varint::from(len).write_to(buf);
 
         buf.put(self.val());
-
-        ()
     }
 }
@@ -1,19 +1,57 @@
 use crate::Varint;
 use bytes::{BufMut, Bytes, BytesMut};
+use std::borrow::Borrow;
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::str;
 use thiserror::Error;
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
-pub struct VarintString(String);
+// A view into the frame it was read from, the UTF-8 is checked once when read.
+#[derive(PartialEq, Eq, Clone)]
+pub struct VarintString(Bytes);
 
 impl VarintString {
     pub fn new(val: &str) -> Self {
-        Self(val.to_owned())
+        Self(Bytes::copy_from_slice(val.as_bytes()))
+    }
+
+    pub fn from_static(val: &'static str) -> Self {
+        Self(Bytes::from_static(val.as_bytes()))
     }
 
     pub fn val(&self) -> &str {
-        self.0.as_ref()
+        // Only built from a &str or from bytes checked by `try_from`.
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+// Hashed like the &str, so that maps keyed by VarintString can be looked up by &str.
+impl Hash for VarintString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.val().hash(state)
+    }
+}
+
+impl Borrow<str> for VarintString {
+    fn borrow(&self) -> &str {
+        self.val()
+    }
+}
+
+impl AsRef<str> for VarintString {
+    fn as_ref(&self) -> &str {
+        self.val()
+    }
+}
+
+impl fmt::Debug for VarintString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("VarintString").field(&self.val()).finish()
     }
 }
 
@@ -34,18 +72,14 @@ impl TryFrom<&mut Bytes> for VarintString {
             .try_into()
             .map_err(|_| VarintStringParseError::InsufficientBytes)?;
         let len = len.u64_val() as usize;
-        let s = if len == 0 {
-            "".to_owned()
-        } else {
-            if bytes.len() < len {
-                return Err(VarintStringParseError::InsufficientBytes);
-            }
-            let b = bytes.split_to(len);
-            let s = str::from_utf8(&b[..]).map_err(|_| VarintStringParseError::Invalid)?;
-            s.to_owned()
-        };
+        if bytes.len() < len {
+            return Err(VarintStringParseError::InsufficientBytes);
+        }
+
+        let b = bytes.split_to(len);
+        str::from_utf8(&b[..]).map_err(|_| VarintStringParseError::Invalid)?;
 
-        Ok(Self(s))
+        Ok(Self(b))
     }
 }
 
@@ -53,10 +87,42 @@ impl VarintString {
     pub fn write_to(&self, buf: &mut BytesMut) {
         let len = self.val().len() as u64;
 
-        buf.extend_from_slice(BytesMut::from(Varint::from(len)).as_ref());
+        Varint::from(len).write_to(buf);
 
         buf.put(self.val().as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_try_from() -> anyhow::Result<()> {
+        let frame = Bytes::from_static(b"\x03GET\x01/");
+        let mut bytes = frame.clone();
+
+        let s = VarintString::try_from(&mut bytes)?;
+        assert_eq!(s.val(), "GET");
+        // Points into the frame instead of a copy.
+        assert_eq!(s.bytes().as_ptr(), frame[1..].as_ptr());
+        assert_eq!(VarintString::try_from(&mut bytes)?, VarintString::new("/"));
+        assert!(bytes.is_empty());
+
+        assert_eq!(
+            VarintString::try_from(&mut Bytes::from_static(b"\x02\xff\xfe")),
+            Err(VarintStringParseError::Invalid)
+        );
+        assert_eq!(
+            VarintString::try_from(&mut Bytes::from_static(b"\x03GE")),
+            Err(VarintStringParseError::InsufficientBytes)
+        );
+
+        let mut map = HashMap::new();
+        map.insert(s, 1);
+        assert_eq!(map.get("GET"), Some(&1));
 
-        ()
+        Ok(())
     }
 }
@@ -16,7 +16,7 @@ fn test() -> anyhow::Result<()> {
         println!("listen_addr {}", listen_addr);
 
         let path = PathBuf::from("/opt/repos/haproxy-spoa-example");
-        let dir = tempdir()?;
+        let _dir = tempdir()?;
 
         let name = "haproxy-spoa-example".to_string();
         let handle = cmd!(
@@ -32,14 +32,11 @@ fn test() -> anyhow::Result<()> {
         let handle_hook = handle.clone();
         let name_hook = name.clone();
 
-        panic::set_hook(Box::new(move |_| {
-            match clean(&handle_hook, &name_hook) {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("{}", e);
-                }
+        panic::set_hook(Box::new(move |_| match clean(&handle_hook, &name_hook) {
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("{}", e);
             }
-            ()
         }));
 
         let haproxy = Task::<anyhow::Result<()>>::local(async move {